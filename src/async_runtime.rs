@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::{
+    io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    sync::{mpsc, oneshot},
+    task::JoinSet,
+};
+
+use crate::{
+    runtime::{decode, Received},
+    Address, Message, MessageId,
+};
+
+/// Async counterpart of the handler traits
+///
+/// Handlers take `&self` so that many requests can be in flight at once, state shared between
+/// them has to be guarded by the implementor.
+///
+pub trait AsyncHandler<A, B, I>: Send + Sync + 'static
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    fn handle(
+        &self,
+        request: Message<A, B, I>,
+        outbox: Outbox<A, B, I>,
+    ) -> impl Future<Output = Vec<Message<A, B, I>>> + Send;
+
+    /// Called once the input is closed, after outstanding `call`s have failed
+    fn on_shutdown(&self, _outbox: Outbox<A, B, I>) -> impl Future<Output = ()> + Send {
+        async {}
+    }
+}
+
+type Waiting<A, B, I> = HashMap<(A, I), oneshot::Sender<Message<A, B, I>>>;
+type Pending<A, B, I> = Arc<Mutex<Waiting<A, B, I>>>;
+
+/// Sends messages from within a handler and awaits replies to them
+///
+pub struct Outbox<A, B, I>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    sender: mpsc::UnboundedSender<Message<A, B, I>>,
+    pending: Pending<A, B, I>,
+}
+
+impl<A, B, I> Clone for Outbox<A, B, I>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    fn clone(&self) -> Self {
+        Self {
+            sender: self.sender.clone(),
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+impl<A, B, I> Outbox<A, B, I>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    pub fn send(&self, message: Message<A, B, I>) -> io::Result<()> {
+        self.sender
+            .send(message)
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "output is closed"))
+    }
+
+    /// Sends a request and resolves with the message replying to its `msg_id`
+    ///
+    /// Fails if the request has no `msg_id` or the input closes before the reply arrives.
+    pub async fn call(&self, request: Message<A, B, I>) -> io::Result<Message<A, B, I>> {
+        let message_id = request
+            .body_field::<I>("msg_id")
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "request has no msg_id"))?;
+        let (sender, receiver) = oneshot::channel();
+        self.lock()
+            .insert((request.destination.clone(), message_id), sender);
+        self.send(request)?;
+        receiver.await.map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "input closed before the reply arrived",
+            )
+        })
+    }
+
+    /// Hands replies to the `call` awaiting them, everything else is returned
+    fn resolve(&self, message: Message<A, B, I>) -> Option<Message<A, B, I>> {
+        let waiting = message
+            .body_field::<I>("in_reply_to")
+            .and_then(|id| self.lock().remove(&(message.source.clone(), id)));
+        match waiting {
+            Some(sender) => {
+                let _ = sender.send(message);
+                None
+            }
+            None => Some(message),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Waiting<A, B, I>> {
+        self.pending
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Drives a node over line delimited JSON, every request is handled in its own task
+///
+/// Returns once `input` is exhausted and every handler has finished, outstanding `call`s fail at
+/// that point since their replies can no longer arrive. `on_shutdown` runs in between.
+pub async fn run<A, B, I, H, R, W>(handler: Arc<H>, input: R, mut output: W) -> io::Result<()>
+where
+    A: Address + DeserializeOwned + Serialize + Send + Sync + 'static,
+    B: DeserializeOwned + Serialize + Send + Sync + 'static,
+    I: MessageId + DeserializeOwned + Serialize + Send + Sync + 'static,
+    H: AsyncHandler<A, B, I>,
+    R: AsyncBufRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let outbox = Outbox {
+        sender,
+        pending: Default::default(),
+    };
+
+    let reader = async move {
+        let mut tasks = JoinSet::new();
+        let mut lines = input.lines();
+        while let Some(line) = lines.next_line().await? {
+            while let Some(finished) = tasks.try_join_next() {
+                propagate_panic(finished);
+            }
+            let request = match decode(&line) {
+                Received::Message(message) => outbox.resolve(message),
+                Received::Rejected(reply) => {
+                    outbox.send(reply)?;
+                    None
+                }
+                Received::Dropped(_) => None,
+            };
+            if let Some(request) = request {
+                let (handler, outbox) = (handler.clone(), outbox.clone());
+                tasks.spawn(async move {
+                    for message in handler.handle(request, outbox.clone()).await {
+                        let _ = outbox.send(message);
+                    }
+                });
+            }
+        }
+        outbox.lock().clear();
+        handler.on_shutdown(outbox.clone()).await;
+        drop(outbox);
+        while let Some(finished) = tasks.join_next().await {
+            propagate_panic(finished);
+        }
+        Ok::<_, io::Error>(())
+    };
+
+    let writer = async move {
+        while let Some(message) = receiver.recv().await {
+            let mut line = serde_json::to_vec(&message)?;
+            line.push(b'\n');
+            output.write_all(&line).await?;
+            output.flush().await?;
+        }
+        Ok::<_, io::Error>(())
+    };
+
+    tokio::try_join!(reader, writer).map(|_| ())
+}
+
+/// Runs `handler` on the process' standard input and output
+pub async fn run_stdio<A, B, I, H>(handler: Arc<H>) -> io::Result<()>
+where
+    A: Address + DeserializeOwned + Serialize + Send + Sync + 'static,
+    B: DeserializeOwned + Serialize + Send + Sync + 'static,
+    I: MessageId + DeserializeOwned + Serialize + Send + Sync + 'static,
+    H: AsyncHandler<A, B, I>,
+{
+    run(
+        handler,
+        BufReader::new(tokio::io::stdin()),
+        tokio::io::stdout(),
+    )
+    .await
+}
+
+fn propagate_panic(finished: Result<(), tokio::task::JoinError>) {
+    if let Err(err) = finished {
+        if err.is_panic() {
+            std::panic::resume_unwind(err.into_panic());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    use crate::{echo::EchoBody, Message, ResponseBuilder};
+
+    use super::{run, AsyncHandler, Outbox};
+
+    type EchoMessage = Message<String, EchoBody<u32>, u32>;
+
+    /// Echoes whatever `n2` answers for the same payload
+    pub struct TestNode;
+
+    impl ResponseBuilder<String, u32, EchoBody<u32>> for TestNode {}
+
+    impl AsyncHandler<String, EchoBody<u32>, u32> for TestNode {
+        async fn handle(
+            &self,
+            request: EchoMessage,
+            outbox: Outbox<String, EchoBody<u32>, u32>,
+        ) -> Vec<EchoMessage> {
+            let Ok(EchoBody::Request { message_id, echo }) = request.body.clone() else {
+                return Vec::new();
+            };
+            let forwarded = Message {
+                source: request.destination.clone(),
+                destination: "n2".to_owned(),
+                body: Ok(EchoBody::Request {
+                    message_id: 100,
+                    echo,
+                }),
+            };
+            let reply = outbox.call(forwarded).await.unwrap();
+            let Ok(EchoBody::Response { echo, .. }) = reply.body else {
+                return Vec::new();
+            };
+            let body = Ok(EchoBody::Response {
+                in_reply_to: message_id,
+                message_id: 101,
+                echo,
+            });
+            vec![TestNode::build_response(&request, body)]
+        }
+    }
+
+    #[tokio::test]
+    async fn test_await_reply_before_responding() {
+        let (mut input, node_input) = tokio::io::duplex(4096);
+        let (node_output, output) = tokio::io::duplex(4096);
+        let node = tokio::spawn(run(
+            Arc::new(TestNode),
+            BufReader::new(node_input),
+            node_output,
+        ));
+        let mut output = BufReader::new(output).lines();
+
+        input
+            .write_all(b"{\"src\":\"c1\",\"dest\":\"n1\",\"body\":{\"type\":\"echo\",\"msg_id\":1,\"echo\":\"hi\"}}\n")
+            .await
+            .unwrap();
+        let forwarded = output.next_line().await.unwrap().unwrap();
+        assert_eq!(
+            forwarded,
+            r#"{"src":"n1","dest":"n2","body":{"type":"echo","msg_id":100,"echo":"hi"}}"#
+        );
+
+        input
+            .write_all(b"{\"src\":\"n2\",\"dest\":\"n1\",\"body\":{\"type\":\"echo_ok\",\"in_reply_to\":100,\"msg_id\":7,\"echo\":\"hi from n2\"}}\n")
+            .await
+            .unwrap();
+        let response = output.next_line().await.unwrap().unwrap();
+        assert_eq!(
+            response,
+            r#"{"src":"n1","dest":"c1","body":{"type":"echo_ok","in_reply_to":1,"msg_id":101,"echo":"hi from n2"}}"#
+        );
+
+        drop(input);
+        node.await.unwrap().unwrap();
+    }
+}
@@ -1,13 +1,33 @@
-use serde::{Deserialize, Serialize};
-use std::{fmt::Debug, hash::Hash};
+pub mod anti_entropy;
+pub mod causal;
+pub mod durable;
+pub mod plumtree;
+pub mod total_order;
 
-use crate::{error::Code, Address, MessageId, MessageIndex, MessageRegistry};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::Arc,
+};
+
+use crate::{
+    error::Code, store::MessageStore, Address, Message, MessageId, MessageIdRegistry,
+    MessageRegistry,
+};
+
+/// Hash of a value that is the same on every node, used to refer to values without sending them
+pub(crate) fn value_hash<T: Hash>(value: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
 #[serde(tag = "type")]
 pub enum BroadcastBody<I, T>
 where
-    I: MessageIndex,
+    I: MessageId,
 {
     #[serde(rename = "broadcast")]
     PushRequest {
@@ -21,26 +41,94 @@ where
         #[serde(rename = "msg_id")]
         message_id: I,
     },
+    /// Plain reads return every value, reads with `since` or `limit` return a page of the values
+    /// in insertion order
     #[serde(rename = "read")]
     ReadRequest {
         #[serde(rename = "msg_id")]
         message_id: I,
+        /// `version` of a previous `read_ok`, only values added after it are returned
+        #[serde(skip_serializing_if = "Option::is_none")]
+        since: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        limit: Option<u64>,
     },
     #[serde(rename = "read_ok")]
     ReadResponse {
         #[serde(rename = "msg_id")]
         message_id: I,
         in_reply_to: I,
-        messages: Vec<T>,
+        messages: Arc<[T]>,
+        /// Where the next page starts, only set for paged reads
+        #[serde(skip_serializing_if = "Option::is_none")]
+        version: Option<u64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        more: Option<bool>,
     },
 }
 
+/// Answers `read` from the stored values, see [`BroadcastBody::ReadRequest`]
+///
+/// Paged reads fail with `NotSupported` if the store doesn't keep insertion order.
+pub(crate) fn respond_read<R, I, T>(
+    node: &mut R,
+    message_id: I,
+    since: Option<u64>,
+    limit: Option<u64>,
+) -> Result<BroadcastBody<I, T>, crate::Error<I>>
+where
+    R: MessageIdRegistry<I> + MessageRegistry<T> + ?Sized,
+    I: MessageId,
+    T: Clone,
+{
+    if since.is_none() && limit.is_none() {
+        return Ok(BroadcastBody::ReadResponse {
+            in_reply_to: message_id,
+            message_id: node.gen_msg_id(),
+            messages: node.messages().snapshot(),
+            version: None,
+            more: None,
+        });
+    }
+    let since = since.unwrap_or(0);
+    let limit = limit.map_or(usize::MAX, |limit| limit as usize);
+    let Some(messages) = node.messages().since(since as usize, limit) else {
+        return Err(crate::Error::new(
+            message_id,
+            Code::NotSupported,
+            "Paged reads need a store keeping insertion order".to_owned(),
+        ));
+    };
+    let version = since + messages.len() as u64;
+    let more = version < node.messages().len() as u64;
+    Ok(BroadcastBody::ReadResponse {
+        in_reply_to: message_id,
+        message_id: node.gen_msg_id(),
+        messages: messages.into(),
+        version: Some(version),
+        more: Some(more),
+    })
+}
+
+/// Reply to a client request plus the messages carrying a pushed value to the peers
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Broadcasted<A, I, T, B>
+where
+    A: Address,
+    I: MessageId + DeserializeOwned + Serialize,
+    B: DeserializeOwned + Serialize,
+{
+    pub response: BroadcastBody<I, T>,
+    pub peers: Vec<Message<A, B, I>>,
+}
+
 /// This trait has to be implement for every Node alongside any workload specific functionality
 ///
-pub trait BroadcastHandler<A, I, T>: MessageId<I> + MessageRegistry<T>
+pub trait BroadcastHandler<A, I, T>: MessageIdRegistry<I> + MessageRegistry<T>
 where
     A: Address,
-    I: MessageIndex,
+    I: MessageId,
     T: Clone,
 {
     fn respond_broadcast(
@@ -59,11 +147,11 @@ where
                     message_id: msg_id,
                 })
             }
-            BroadcastBody::ReadRequest { message_id } => Ok(BroadcastBody::ReadResponse {
-                in_reply_to: message_id,
-                message_id: self.gen_msg_id(),
-                messages: self.messages().to_owned(),
-            }),
+            BroadcastBody::ReadRequest {
+                message_id,
+                since,
+                limit,
+            } => respond_read(self, message_id, since, limit),
             BroadcastBody::PushResponse { message_id, .. } => Err(crate::Error::new(
                 message_id,
                 Code::MalformedRequest,
@@ -82,7 +170,8 @@ where
 mod test {
 
     use crate::{
-        broadcast::BroadcastBody, Message, MessageId, MessageRegistry, NodeId, ResponseBuilder,
+        broadcast::BroadcastBody, store::IndexMessages, Message, MessageIdRegistry,
+        MessageRegistry, NodeIdRegistry, ResponseBuilder,
     };
 
     use super::BroadcastHandler;
@@ -91,17 +180,17 @@ mod test {
     pub struct TestNode {
         n: u32,
         id: String,
-        messages: Vec<u32>,
+        messages: IndexMessages<u32>,
     }
 
-    impl MessageId<u32> for TestNode {
+    impl MessageIdRegistry<u32> for TestNode {
         fn gen_msg_id(&mut self) -> u32 {
             self.n += 1;
             self.n
         }
     }
 
-    impl NodeId<String, u32> for TestNode {
+    impl NodeIdRegistry<String, u32> for TestNode {
         fn node_id(&self) -> &String {
             &self.id
         }
@@ -113,11 +202,12 @@ mod test {
     }
 
     impl MessageRegistry<u32> for TestNode {
-        fn push_msg(&mut self, msg: u32) {
-            self.messages.push(msg);
+        type Store = IndexMessages<u32>;
+        fn messages(&self) -> &IndexMessages<u32> {
+            &self.messages
         }
-        fn messages(&self) -> &[u32] {
-            self.messages.as_slice()
+        fn messages_mut(&mut self) -> &mut IndexMessages<u32> {
+            &mut self.messages
         }
     }
 
@@ -169,7 +259,7 @@ mod test {
           }
         } "#;
         let mut test_node = TestNode::default();
-        let expected = r#"{"src":"n1","dest":"c1","body":{"type":"read_ok","msg_id":4,"in_reply_to":1,"messages":[1000,1000,1000]}}"#;
+        let expected = r#"{"src":"n1","dest":"c1","body":{"type":"read_ok","msg_id":4,"in_reply_to":1,"messages":[1000]}}"#;
         let request: Message<String, BroadcastBody<u32, u32>, u32> =
             serde_json::from_str(request).unwrap();
         for _ in 0..3 {
@@ -188,4 +278,31 @@ mod test {
         let res = serde_json::to_string(&res).unwrap();
         assert_eq!(expected, res);
     }
+
+    #[test]
+    fn test_broadcast_paged_read() {
+        let mut test_node = TestNode::default();
+        for message in [10, 20, 30] {
+            test_node
+                .respond_broadcast(BroadcastBody::PushRequest {
+                    message_id: 1,
+                    message,
+                })
+                .unwrap();
+        }
+        let read = r#"{"type":"read","msg_id":1,"limit":2}"#;
+        let read: BroadcastBody<u32, u32> = serde_json::from_str(read).unwrap();
+        let res = serde_json::to_string(&test_node.respond_broadcast(read).unwrap()).unwrap();
+        assert_eq!(
+            res,
+            r#"{"type":"read_ok","msg_id":4,"in_reply_to":1,"messages":[10,20],"version":2,"more":true}"#
+        );
+        let read = r#"{"type":"read","msg_id":2,"since":2,"limit":2}"#;
+        let read: BroadcastBody<u32, u32> = serde_json::from_str(read).unwrap();
+        let res = serde_json::to_string(&test_node.respond_broadcast(read).unwrap()).unwrap();
+        assert_eq!(
+            res,
+            r#"{"type":"read_ok","msg_id":5,"in_reply_to":2,"messages":[30],"version":3,"more":false}"#
+        );
+    }
 }
@@ -0,0 +1,299 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashSet, fmt::Debug, hash::Hash};
+
+use crate::{
+    broadcast::value_hash, error::Code, store::MessageStore, Address, Message, MessageId,
+    MessageIdRegistry, MessageRegistry, NodeIdRegistry, TopologyRegistry,
+};
+
+/// Body exchanged between neighbours reconciling their values
+///
+/// The initiator sends a `digest` of its values hashed into buckets. The neighbour answers with
+/// the buckets that differ along with its own values in them, and the initiator completes the
+/// exchange by sending back only the values in those buckets the neighbour lacks.
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[serde(tag = "type")]
+pub enum AntiEntropyBody<I, T>
+where
+    I: MessageId,
+{
+    #[serde(rename = "digest")]
+    DigestRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        digest: Vec<u64>,
+    },
+    #[serde(rename = "digest_ok")]
+    DigestResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        buckets: Vec<usize>,
+        messages: Vec<T>,
+    },
+    #[serde(rename = "fill")]
+    Fill {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        messages: Vec<T>,
+    },
+}
+
+pub type AntiEntropyMessage<A, I, T> = Message<A, AntiEntropyBody<I, T>, I>;
+
+/// Periodic push-pull reconciliation of the [`MessageRegistry`] with the [`TopologyRegistry`]
+/// neighbours
+///
+/// Only the values in buckets whose digests differ are sent, so nodes that are mostly in sync
+/// exchange little more than the digests.
+///
+pub trait AntiEntropyHandler<A, I, T>:
+    NodeIdRegistry<A, I> + MessageIdRegistry<I> + MessageRegistry<T> + TopologyRegistry<A>
+where
+    A: Address + DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+    T: Clone + Eq + Hash + DeserializeOwned + Serialize,
+{
+    /// Number of buckets values are hashed into, has to be the same on every node
+    ///
+    /// Zero is treated as a single bucket.
+    fn digest_buckets(&self) -> usize {
+        64
+    }
+
+    /// XOR of the value hashes in each bucket
+    fn digest(&self) -> Vec<u64> {
+        let count = self.digest_buckets().max(1);
+        let mut digest = vec![0; count];
+        for value in self.messages().iter() {
+            let hash = value_hash(value);
+            digest[hash as usize % count] ^= hash;
+        }
+        digest
+    }
+
+    /// Sends the digest to every neighbour, to be called periodically
+    fn start_anti_entropy(&mut self) -> Vec<AntiEntropyMessage<A, I, T>> {
+        let digest = self.digest();
+        let node = self.node_id().clone();
+        let mut outgoing = Vec::new();
+        for neighbour in self.topology().to_vec() {
+            outgoing.push(Message {
+                source: node.clone(),
+                destination: neighbour,
+                body: Ok(AntiEntropyBody::DigestRequest {
+                    message_id: self.gen_msg_id(),
+                    digest: digest.clone(),
+                }),
+            });
+        }
+        outgoing
+    }
+
+    /// Handles a message from a neighbour, returning the messages continuing the exchange
+    fn handle_anti_entropy(
+        &mut self,
+        message: AntiEntropyMessage<A, I, T>,
+    ) -> Vec<AntiEntropyMessage<A, I, T>> {
+        let Message {
+            source,
+            destination,
+            body: Ok(body),
+        } = message
+        else {
+            return Vec::new();
+        };
+        let body = match body {
+            AntiEntropyBody::DigestRequest { message_id, digest } => {
+                let own = self.digest();
+                if digest.len() != own.len() {
+                    Err(crate::Error::new(
+                        message_id,
+                        Code::MalformedRequest,
+                        format!("Expected {} buckets, got {}", own.len(), digest.len()),
+                    ))
+                } else {
+                    let buckets: Vec<usize> = (0..own.len())
+                        .filter(|bucket| own[*bucket] != digest[*bucket])
+                        .collect();
+                    Ok(AntiEntropyBody::DigestResponse {
+                        in_reply_to: message_id,
+                        message_id: self.gen_msg_id(),
+                        messages: self.bucket_values(&buckets),
+                        buckets,
+                    })
+                }
+            }
+            AntiEntropyBody::DigestResponse {
+                buckets, messages, ..
+            } => {
+                if buckets.is_empty() {
+                    return Vec::new();
+                }
+                let theirs: HashSet<T> = messages.iter().cloned().collect();
+                let missing: Vec<T> = self
+                    .bucket_values(&buckets)
+                    .into_iter()
+                    .filter(|value| !theirs.contains(value))
+                    .collect();
+                self.merge_values(messages);
+                if missing.is_empty() {
+                    return Vec::new();
+                }
+                Ok(AntiEntropyBody::Fill {
+                    message_id: self.gen_msg_id(),
+                    messages: missing,
+                })
+            }
+            AntiEntropyBody::Fill { messages, .. } => {
+                self.merge_values(messages);
+                return Vec::new();
+            }
+        };
+        vec![Message {
+            source: destination,
+            destination: source,
+            body,
+        }]
+    }
+
+    /// Values stored in any of `buckets`
+    fn bucket_values(&self, buckets: &[usize]) -> Vec<T> {
+        let count = self.digest_buckets().max(1);
+        let buckets: HashSet<usize> = buckets.iter().copied().collect();
+        self.messages()
+            .iter()
+            .filter(|value| buckets.contains(&(value_hash(*value) as usize % count)))
+            .cloned()
+            .collect()
+    }
+
+    fn merge_values(&mut self, values: Vec<T>) {
+        for value in values {
+            self.push_msg(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        store::IndexMessages, MessageIdRegistry, MessageRegistry, NodeIdRegistry, TopologyRegistry,
+    };
+
+    use super::{AntiEntropyBody, AntiEntropyHandler};
+
+    pub struct TestNode {
+        n: u32,
+        id: String,
+        topology: Vec<String>,
+        messages: IndexMessages<u32>,
+        buckets: usize,
+    }
+
+    impl TestNode {
+        fn new(id: &str, neighbour: &str, messages: &[u32]) -> Self {
+            Self {
+                n: 0,
+                id: id.to_owned(),
+                topology: vec![neighbour.to_owned()],
+                messages: messages.iter().copied().collect(),
+                buckets: 64,
+            }
+        }
+    }
+
+    impl MessageIdRegistry<u32> for TestNode {
+        fn gen_msg_id(&mut self) -> u32 {
+            self.n += 1;
+            self.n
+        }
+    }
+
+    impl NodeIdRegistry<String, u32> for TestNode {
+        fn node_id(&self) -> &String {
+            &self.id
+        }
+
+        fn set_node_id(&mut self, id: String) -> Result<(), crate::Error<u32>> {
+            self.id = id;
+            Ok(())
+        }
+    }
+
+    impl MessageRegistry<u32> for TestNode {
+        type Store = IndexMessages<u32>;
+        fn messages(&self) -> &IndexMessages<u32> {
+            &self.messages
+        }
+        fn messages_mut(&mut self) -> &mut IndexMessages<u32> {
+            &mut self.messages
+        }
+    }
+
+    impl TopologyRegistry<String> for TestNode {
+        fn set_topology(&mut self, topology: Vec<String>) {
+            self.topology = topology;
+        }
+        fn topology(&self) -> &[String] {
+            self.topology.as_slice()
+        }
+    }
+
+    impl AntiEntropyHandler<String, u32, u32> for TestNode {
+        fn digest_buckets(&self) -> usize {
+            self.buckets
+        }
+    }
+
+    #[test]
+    fn test_anti_entropy_sends_only_missing_values() {
+        let shared: Vec<u32> = (0..500).collect();
+        let mut n1 = TestNode::new("n1", "n2", &shared);
+        let mut n2 = TestNode::new("n2", "n1", &shared);
+        n1.push_msg(1000);
+        n1.push_msg(1001);
+        n2.push_msg(2000);
+
+        let digest = n1.start_anti_entropy().remove(0);
+        let reply = n2.handle_anti_entropy(digest).remove(0);
+        let Ok(AntiEntropyBody::DigestResponse {
+            ref buckets,
+            ref messages,
+            ..
+        }) = reply.body
+        else {
+            panic!("expected digest_ok");
+        };
+        assert!(buckets.len() <= 3);
+        assert!(messages.len() < 50);
+
+        let fill = n1.handle_anti_entropy(reply).remove(0);
+        let Ok(AntiEntropyBody::Fill { ref messages, .. }) = fill.body else {
+            panic!("expected fill");
+        };
+        assert_eq!(messages, &[1000, 1001]);
+        n2.handle_anti_entropy(fill);
+
+        assert_eq!(n1.digest(), n2.digest());
+        let digest = n2.start_anti_entropy().remove(0);
+        let reply = n1.handle_anti_entropy(digest).remove(0);
+        assert!(n2.handle_anti_entropy(reply).is_empty());
+    }
+
+    #[test]
+    fn test_anti_entropy_without_buckets() {
+        let mut n1 = TestNode::new("n1", "n2", &[1, 2]);
+        let mut n2 = TestNode::new("n2", "n1", &[3]);
+        n1.buckets = 0;
+        n2.buckets = 0;
+        assert_eq!(n1.digest().len(), 1);
+
+        let digest = n1.start_anti_entropy().remove(0);
+        let reply = n2.handle_anti_entropy(digest).remove(0);
+        let fill = n1.handle_anti_entropy(reply).remove(0);
+        n2.handle_anti_entropy(fill);
+        assert_eq!(n1.digest(), n2.digest());
+    }
+}
@@ -0,0 +1,405 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashMap, fmt::Debug};
+
+use crate::{
+    broadcast::{respond_read, BroadcastBody, Broadcasted},
+    clock::VectorTimestamp,
+    error::Code,
+    Address, CausalRegistry, ClusterRegistry, Message, MessageId, MessageIdRegistry,
+    MessageRegistry,
+};
+
+/// Body exchanged between nodes in causal broadcast mode
+///
+/// `clock` counts the values of every origin delivered at `origin` when `message` was broadcast,
+/// including `message` itself.
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(bound(
+    serialize = "A: Serialize, I: Serialize, T: Serialize",
+    deserialize = "A: DeserializeOwned, I: DeserializeOwned, T: DeserializeOwned"
+))]
+pub enum CausalBody<A, I, T>
+where
+    A: Address,
+    I: MessageId,
+{
+    #[serde(rename = "causal_broadcast")]
+    DeliverRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        origin: A,
+        clock: VectorTimestamp<A>,
+        message: T,
+    },
+    #[serde(rename = "causal_broadcast_ok")]
+    DeliverResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+    },
+}
+
+pub type CausalMessage<A, I, T> = Message<A, CausalBody<A, I, T>, I>;
+pub type CausalBroadcasted<A, I, T> = Broadcasted<A, I, T, CausalBody<A, I, T>>;
+
+/// Vector clock of delivered values, the values still waiting on their dependencies and the
+/// values sent to peers that weren't acknowledged yet
+///
+/// A value broadcast by `origin` is deliverable once every value `origin` had delivered before it
+/// has been delivered here as well, so every value has to reach every peer. Sends are identified
+/// by the peer and their `msg_id` and retried until acknowledged.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CausalBroadcast<A: Address, I: MessageId, T> {
+    delivered: VectorTimestamp<A>,
+    buffer: Vec<(A, VectorTimestamp<A>, T)>,
+    in_flight: HashMap<(A, I), (VectorTimestamp<A>, T)>,
+}
+
+impl<A: Address, I: MessageId, T> Default for CausalBroadcast<A, I, T> {
+    fn default() -> Self {
+        Self {
+            delivered: VectorTimestamp::default(),
+            buffer: Vec::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+}
+
+impl<A: Address, I: MessageId, T: Clone> CausalBroadcast<A, I, T> {
+    /// Values delivered so far, per origin
+    pub fn clock(&self) -> &VectorTimestamp<A> {
+        &self.delivered
+    }
+
+    /// Values received but not deliverable yet
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Values sent to peers and not acknowledged yet
+    pub fn unacknowledged(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Delivers a value originating at `node` and returns the timestamp to send it with
+    pub fn broadcast(&mut self, node: &A) -> VectorTimestamp<A> {
+        self.delivered.increment(node);
+        self.delivered.clone()
+    }
+
+    fn track(&mut self, peer: A, message_id: I, clock: VectorTimestamp<A>, value: T) {
+        self.in_flight.insert((peer, message_id), (clock, value));
+    }
+
+    fn ack(&mut self, peer: A, in_reply_to: I) {
+        self.in_flight.remove(&(peer, in_reply_to));
+    }
+
+    /// Sends not acknowledged yet
+    fn outstanding(&self) -> Vec<(A, I, VectorTimestamp<A>, T)> {
+        self.in_flight
+            .iter()
+            .map(|((peer, message_id), (clock, value))| {
+                (
+                    peer.clone(),
+                    message_id.clone(),
+                    clock.clone(),
+                    value.clone(),
+                )
+            })
+            .collect()
+    }
+
+    /// Buffers a value from `origin` and returns every value that became deliverable, in a causal
+    /// order
+    ///
+    /// Values that were delivered before are ignored.
+    pub fn receive(&mut self, origin: A, clock: VectorTimestamp<A>, value: T) -> Vec<T> {
+        if clock.get(&origin) <= self.delivered.get(&origin) {
+            return Vec::new();
+        }
+        self.buffer.push((origin, clock, value));
+        let mut deliverable = Vec::new();
+        while let Some(index) = self
+            .buffer
+            .iter()
+            .position(|(origin, clock, _)| self.is_deliverable(origin, clock))
+        {
+            let (origin, _, value) = self.buffer.swap_remove(index);
+            self.delivered.increment(&origin);
+            deliverable.push(value);
+        }
+        let delivered = &self.delivered;
+        self.buffer
+            .retain(|(origin, clock, _)| clock.get(origin) > delivered.get(origin));
+        deliverable
+    }
+
+    fn is_deliverable(&self, origin: &A, clock: &VectorTimestamp<A>) -> bool {
+        clock.get(origin) == self.delivered.get(origin) + 1
+            && clock
+                .iter()
+                .all(|(node, count)| node == origin || count <= self.delivered.get(node))
+    }
+}
+
+/// Broadcast variant delivering values to the [`MessageRegistry`] in causal order
+///
+/// Clients keep using [`BroadcastBody`], values they push are stamped and sent to every peer as
+/// [`CausalBody::DeliverRequest`]. [`CausalBroadcastHandler::retry_causal`] has to be called
+/// periodically to resend the ones that weren't acknowledged.
+///
+pub trait CausalBroadcastHandler<A, I, T>:
+    ClusterRegistry<A, I> + MessageIdRegistry<I> + MessageRegistry<T> + CausalRegistry<A, I, T>
+where
+    A: Address + DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+    T: Clone + DeserializeOwned + Serialize,
+{
+    /// Answers a client request, pushed values are delivered locally before being sent to the peers
+    fn respond_causal_broadcast(
+        &mut self,
+        request: BroadcastBody<I, T>,
+    ) -> Result<CausalBroadcasted<A, I, T>, crate::Error<I>> {
+        match request {
+            BroadcastBody::PushRequest {
+                message_id,
+                message,
+            } => {
+                let origin = self.node_id().clone();
+                let clock = self.causal_broadcast().broadcast(&origin);
+                self.push_msg(message.clone());
+                let mut peers = Vec::new();
+                for peer in self.peers() {
+                    let message_id = self.gen_msg_id();
+                    self.causal_broadcast().track(
+                        peer.clone(),
+                        message_id.clone(),
+                        clock.clone(),
+                        message.clone(),
+                    );
+                    peers.push(Message {
+                        source: origin.clone(),
+                        destination: peer,
+                        body: Ok(CausalBody::DeliverRequest {
+                            message_id,
+                            origin: origin.clone(),
+                            clock: clock.clone(),
+                            message: message.clone(),
+                        }),
+                    });
+                }
+                let response = BroadcastBody::PushResponse {
+                    in_reply_to: message_id,
+                    message_id: self.gen_msg_id(),
+                };
+                Ok(Broadcasted { response, peers })
+            }
+            BroadcastBody::ReadRequest {
+                message_id,
+                since,
+                limit,
+            } => Ok(Broadcasted {
+                response: respond_read(self, message_id, since, limit)?,
+                peers: Vec::new(),
+            }),
+            BroadcastBody::PushResponse { message_id, .. }
+            | BroadcastBody::ReadResponse { message_id, .. } => Err(crate::Error::new(
+                message_id,
+                Code::MalformedRequest,
+                "Request is response".to_owned(),
+            )),
+        }
+    }
+
+    /// Acknowledges a value from a peer, delivering it once its dependencies have been delivered,
+    /// and counts the acks of the values this node sent
+    fn handle_causal(&mut self, message: CausalMessage<A, I, T>) -> Vec<CausalMessage<A, I, T>> {
+        match message.body {
+            Ok(CausalBody::DeliverRequest {
+                message_id,
+                origin,
+                clock,
+                message: value,
+            }) => {
+                for value in self.causal_broadcast().receive(origin, clock, value) {
+                    self.push_msg(value);
+                }
+                vec![Message {
+                    source: message.destination,
+                    destination: message.source,
+                    body: Ok(CausalBody::DeliverResponse {
+                        in_reply_to: message_id,
+                        message_id: self.gen_msg_id(),
+                    }),
+                }]
+            }
+            Ok(CausalBody::DeliverResponse { in_reply_to, .. }) => {
+                self.causal_broadcast().ack(message.source, in_reply_to);
+                Vec::new()
+            }
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Resends the values peers haven't acknowledged yet, to be called periodically
+    fn retry_causal(&mut self) -> Vec<CausalMessage<A, I, T>> {
+        let origin = self.node_id().clone();
+        self.causal_broadcast()
+            .outstanding()
+            .into_iter()
+            .map(|(peer, message_id, clock, message)| Message {
+                source: origin.clone(),
+                destination: peer,
+                body: Ok(CausalBody::DeliverRequest {
+                    message_id,
+                    origin: origin.clone(),
+                    clock,
+                    message,
+                }),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        broadcast::BroadcastBody,
+        store::{IndexMessages, MessageStore},
+        CausalRegistry, ClusterRegistry, MessageIdRegistry, MessageRegistry, NodeIdRegistry,
+    };
+
+    use super::{CausalBroadcast, CausalBroadcastHandler};
+
+    pub struct TestNode {
+        n: u32,
+        id: String,
+        node_ids: Vec<String>,
+        messages: IndexMessages<u32>,
+        causal: CausalBroadcast<String, u32, u32>,
+    }
+
+    impl TestNode {
+        fn new(id: &str) -> Self {
+            Self {
+                n: 0,
+                id: id.to_owned(),
+                node_ids: vec!["n1".to_owned(), "n2".to_owned(), "n3".to_owned()],
+                messages: IndexMessages::default(),
+                causal: CausalBroadcast::default(),
+            }
+        }
+    }
+
+    impl MessageIdRegistry<u32> for TestNode {
+        fn gen_msg_id(&mut self) -> u32 {
+            self.n += 1;
+            self.n
+        }
+    }
+
+    impl NodeIdRegistry<String, u32> for TestNode {
+        fn node_id(&self) -> &String {
+            &self.id
+        }
+
+        fn set_node_id(&mut self, id: String) -> Result<(), crate::Error<u32>> {
+            self.id = id;
+            Ok(())
+        }
+    }
+
+    impl ClusterRegistry<String, u32> for TestNode {
+        fn set_node_ids(&mut self, node_ids: Vec<String>) {
+            self.node_ids = node_ids;
+        }
+
+        fn node_ids(&self) -> &[String] {
+            &self.node_ids
+        }
+    }
+
+    impl MessageRegistry<u32> for TestNode {
+        type Store = IndexMessages<u32>;
+        fn messages(&self) -> &IndexMessages<u32> {
+            &self.messages
+        }
+        fn messages_mut(&mut self) -> &mut IndexMessages<u32> {
+            &mut self.messages
+        }
+    }
+
+    impl CausalRegistry<String, u32, u32> for TestNode {
+        fn causal_broadcast(&mut self) -> &mut CausalBroadcast<String, u32, u32> {
+            &mut self.causal
+        }
+    }
+
+    impl CausalBroadcastHandler<String, u32, u32> for TestNode {}
+
+    #[test]
+    fn test_causal_delivery_waits_for_dependencies() {
+        let mut n1 = TestNode::new("n1");
+        let mut n2 = TestNode::new("n2");
+        let mut n3 = TestNode::new("n3");
+
+        let push = |message| BroadcastBody::PushRequest {
+            message_id: 1,
+            message,
+        };
+        let from_n1 = n1.respond_causal_broadcast(push(1)).unwrap().peers;
+        n2.handle_causal(from_n1[0].clone());
+        let from_n2 = n2.respond_causal_broadcast(push(2)).unwrap().peers;
+
+        let to_n3 = from_n2[1].clone();
+        assert_eq!(to_n3.destination, "n3");
+        let acks = n3.handle_causal(to_n3);
+        assert!(n3.messages().is_empty());
+        assert_eq!(n3.causal.buffered(), 1);
+        let ack = serde_json::to_string(&acks).unwrap();
+        assert_eq!(
+            ack,
+            r#"[{"src":"n3","dest":"n2","body":{"type":"causal_broadcast_ok","in_reply_to":3,"msg_id":1}}]"#
+        );
+
+        n3.handle_causal(from_n1[1].clone());
+        n3.handle_causal(from_n1[1].clone());
+        assert_eq!(*n3.messages().snapshot(), [1, 2]);
+        assert_eq!(n3.causal.buffered(), 0);
+    }
+
+    #[test]
+    fn test_causal_retries_until_acknowledged() {
+        let mut n1 = TestNode::new("n1");
+        let mut n2 = TestNode::new("n2");
+
+        let push = BroadcastBody::PushRequest {
+            message_id: 1,
+            message: 7,
+        };
+        let lost = n1.respond_causal_broadcast(push).unwrap().peers;
+        assert_eq!(n1.causal.unacknowledged(), 2);
+
+        let retried = n1.retry_causal();
+        assert_eq!(retried.len(), 2);
+        let to_n2 = retried
+            .into_iter()
+            .find(|message| message.destination == "n2")
+            .unwrap();
+        assert_eq!(
+            Some(to_n2.clone()),
+            lost.into_iter().find(|m| m.destination == "n2")
+        );
+
+        for ack in n2.handle_causal(to_n2) {
+            n1.handle_causal(ack);
+        }
+        assert_eq!(*n2.messages().snapshot(), [7]);
+        assert_eq!(n1.causal.unacknowledged(), 1);
+        assert_eq!(n1.retry_causal()[0].destination, "n3");
+    }
+}
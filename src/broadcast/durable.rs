@@ -0,0 +1,428 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashMap, fmt::Debug};
+
+use crate::{
+    broadcast::{respond_read, BroadcastBody},
+    error::Code,
+    reply::ReplyHandle,
+    Address, ClusterRegistry, DurableRegistry, Message, MessageId, MessageIdRegistry,
+    MessageRegistry,
+};
+
+/// Body exchanged between nodes replicating a pushed value before it is acknowledged
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[serde(tag = "type")]
+pub enum DurableBody<I, T>
+where
+    I: MessageId,
+{
+    #[serde(rename = "replicate")]
+    ReplicateRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        message: T,
+    },
+    #[serde(rename = "replicate_ok")]
+    ReplicateResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+    },
+}
+
+pub type BroadcastMessage<A, I, T> = Message<A, BroadcastBody<I, T>, I>;
+pub type DurableMessage<A, I, T> = Message<A, DurableBody<I, T>, I>;
+
+/// Messages to send after a [`DurableBroadcastHandler`] step
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Replicated<A, I, T>
+where
+    A: Address,
+    I: MessageId + DeserializeOwned + Serialize,
+    T: DeserializeOwned + Serialize,
+{
+    /// Replies to clients whose requests are complete
+    pub replies: Vec<BroadcastMessage<A, I, T>>,
+    pub peers: Vec<DurableMessage<A, I, T>>,
+}
+
+impl<A, I, T> Default for Replicated<A, I, T>
+where
+    A: Address,
+    I: MessageId + DeserializeOwned + Serialize,
+    T: DeserializeOwned + Serialize,
+{
+    fn default() -> Self {
+        Self {
+            replies: Vec::new(),
+            peers: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Pending<A: Address, I: MessageId, T> {
+    reply: Option<ReplyHandle<A, I>>,
+    message: T,
+    required: usize,
+    acked: usize,
+    sent: usize,
+}
+
+/// Pushes waiting for peers to acknowledge their value
+///
+/// Each replicate request is identified by the peer it was sent to and its `msg_id`, so an ack
+/// counts once no matter how often the request was retried. The client is answered once enough
+/// peers acknowledged, the remaining peers are retried until they acknowledge as well.
+///
+#[derive(Clone, Debug)]
+pub struct Durable<A: Address, I: MessageId, T> {
+    acks: usize,
+    next: u64,
+    pending: HashMap<u64, Pending<A, I, T>>,
+    in_flight: HashMap<(A, I), u64>,
+}
+
+impl<A: Address, I: MessageId, T: Clone> Durable<A, I, T> {
+    /// Replies to pushes once `acks` peers hold the value, or every peer in smaller clusters
+    pub fn new(acks: usize) -> Self {
+        Self {
+            acks,
+            next: 0,
+            pending: HashMap::new(),
+            in_flight: HashMap::new(),
+        }
+    }
+
+    pub fn acks(&self) -> usize {
+        self.acks
+    }
+
+    /// Pushes with replicate requests still waiting for acks
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Starts waiting for the replicate requests in `sent`, hands `reply` back if no acks are
+    /// needed
+    fn track(
+        &mut self,
+        reply: ReplyHandle<A, I>,
+        message: T,
+        sent: Vec<(A, I)>,
+    ) -> Option<ReplyHandle<A, I>> {
+        let required = self.acks.min(sent.len());
+        if required == 0 {
+            return Some(reply);
+        }
+        let key = self.next;
+        self.next += 1;
+        let pending = Pending {
+            reply: Some(reply),
+            message,
+            required,
+            acked: 0,
+            sent: sent.len(),
+        };
+        self.in_flight
+            .extend(sent.into_iter().map(|sent| (sent, key)));
+        self.pending.insert(key, pending);
+        None
+    }
+
+    /// Counts an ack from `peer`, returning the reply of the push it completes
+    fn ack(&mut self, peer: A, in_reply_to: I) -> Option<ReplyHandle<A, I>> {
+        let key = self.in_flight.remove(&(peer, in_reply_to))?;
+        let pending = self.pending.get_mut(&key)?;
+        pending.acked += 1;
+        let reply = if pending.acked >= pending.required {
+            pending.reply.take()
+        } else {
+            None
+        };
+        if pending.acked == pending.sent {
+            self.pending.remove(&key);
+        }
+        reply
+    }
+
+    /// Replicate requests not acknowledged yet
+    fn outstanding(&self) -> Vec<(A, I, T)> {
+        self.in_flight
+            .iter()
+            .filter_map(|((peer, message_id), key)| {
+                let pending = self.pending.get(key)?;
+                Some((peer.clone(), message_id.clone(), pending.message.clone()))
+            })
+            .collect()
+    }
+}
+
+/// Broadcast variant acknowledging a push only once [`Durable::acks`] peers stored the value
+///
+/// Clients keep using [`BroadcastBody`]. A crash of the receiving node after `broadcast_ok` no
+/// longer loses the value, as long as one of the acknowledging peers survives.
+///
+pub trait DurableBroadcastHandler<A, I, T>:
+    ClusterRegistry<A, I> + MessageIdRegistry<I> + MessageRegistry<T> + DurableRegistry<A, I, T>
+where
+    A: Address + DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+    T: Clone + DeserializeOwned + Serialize,
+{
+    /// Answers a client request, pushes are answered by [`Self::handle_durable`] once enough peers
+    /// acknowledged them
+    fn respond_durable_broadcast(
+        &mut self,
+        request: &BroadcastMessage<A, I, T>,
+    ) -> Result<Replicated<A, I, T>, crate::Error<I>> {
+        let Some(reply) = ReplyHandle::new(request) else {
+            return Ok(Replicated::default());
+        };
+        match &request.body {
+            Ok(BroadcastBody::PushRequest { message, .. }) => {
+                self.push_msg(message.clone());
+                let mut sent = Vec::new();
+                let mut peers = Vec::new();
+                for peer in self.peers() {
+                    let message_id = self.gen_msg_id();
+                    sent.push((peer.clone(), message_id.clone()));
+                    peers.push(Message {
+                        source: request.destination.clone(),
+                        destination: peer,
+                        body: Ok(DurableBody::ReplicateRequest {
+                            message_id,
+                            message: message.clone(),
+                        }),
+                    });
+                }
+                let replies = self
+                    .durable()
+                    .track(reply, message.clone(), sent)
+                    .map(|reply| self.push_reply(reply))
+                    .into_iter()
+                    .collect();
+                Ok(Replicated { replies, peers })
+            }
+            Ok(BroadcastBody::ReadRequest {
+                message_id,
+                since,
+                limit,
+            }) => {
+                let response = respond_read(self, message_id.clone(), *since, *limit);
+                Ok(Replicated {
+                    replies: vec![reply.complete(response)],
+                    peers: Vec::new(),
+                })
+            }
+            Ok(_) | Err(_) => Err(crate::Error::new(
+                reply.in_reply_to().clone(),
+                Code::MalformedRequest,
+                "Request is response".to_owned(),
+            )),
+        }
+    }
+
+    /// Stores values replicated by peers and counts their acks
+    fn handle_durable(&mut self, message: DurableMessage<A, I, T>) -> Replicated<A, I, T> {
+        match message.body {
+            Ok(DurableBody::ReplicateRequest {
+                message_id,
+                message: value,
+            }) => {
+                self.push_msg(value);
+                let ack = Message {
+                    source: message.destination,
+                    destination: message.source,
+                    body: Ok(DurableBody::ReplicateResponse {
+                        in_reply_to: message_id,
+                        message_id: self.gen_msg_id(),
+                    }),
+                };
+                Replicated {
+                    replies: Vec::new(),
+                    peers: vec![ack],
+                }
+            }
+            Ok(DurableBody::ReplicateResponse { in_reply_to, .. }) => {
+                let replies = self
+                    .durable()
+                    .ack(message.source, in_reply_to)
+                    .map(|reply| self.push_reply(reply))
+                    .into_iter()
+                    .collect();
+                Replicated {
+                    replies,
+                    peers: Vec::new(),
+                }
+            }
+            Err(_) => Replicated::default(),
+        }
+    }
+
+    /// Resends the replicate requests not acknowledged yet, to be called periodically
+    fn retry_durable(&mut self) -> Vec<DurableMessage<A, I, T>> {
+        let source = self.node_id().clone();
+        self.durable()
+            .outstanding()
+            .into_iter()
+            .map(|(peer, message_id, message)| Message {
+                source: source.clone(),
+                destination: peer,
+                body: Ok(DurableBody::ReplicateRequest {
+                    message_id,
+                    message,
+                }),
+            })
+            .collect()
+    }
+
+    fn push_reply(&mut self, reply: ReplyHandle<A, I>) -> BroadcastMessage<A, I, T> {
+        let body = BroadcastBody::PushResponse {
+            in_reply_to: reply.in_reply_to().clone(),
+            message_id: self.gen_msg_id(),
+        };
+        reply.complete(Ok(body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        broadcast::BroadcastBody,
+        store::{IndexMessages, MessageStore},
+        ClusterRegistry, DurableRegistry, Message, MessageIdRegistry, MessageRegistry,
+        NodeIdRegistry,
+    };
+
+    use super::{Durable, DurableBroadcastHandler};
+
+    pub struct TestNode {
+        n: u32,
+        id: String,
+        node_ids: Vec<String>,
+        messages: IndexMessages<u32>,
+        durable: Durable<String, u32, u32>,
+    }
+
+    impl TestNode {
+        fn new(id: &str) -> Self {
+            Self {
+                n: 0,
+                id: id.to_owned(),
+                node_ids: vec!["n1".to_owned(), "n2".to_owned(), "n3".to_owned()],
+                messages: IndexMessages::default(),
+                durable: Durable::new(2),
+            }
+        }
+    }
+
+    impl MessageIdRegistry<u32> for TestNode {
+        fn gen_msg_id(&mut self) -> u32 {
+            self.n += 1;
+            self.n
+        }
+    }
+
+    impl NodeIdRegistry<String, u32> for TestNode {
+        fn node_id(&self) -> &String {
+            &self.id
+        }
+
+        fn set_node_id(&mut self, id: String) -> Result<(), crate::Error<u32>> {
+            self.id = id;
+            Ok(())
+        }
+    }
+
+    impl ClusterRegistry<String, u32> for TestNode {
+        fn set_node_ids(&mut self, node_ids: Vec<String>) {
+            self.node_ids = node_ids;
+        }
+
+        fn node_ids(&self) -> &[String] {
+            &self.node_ids
+        }
+    }
+
+    impl MessageRegistry<u32> for TestNode {
+        type Store = IndexMessages<u32>;
+        fn messages(&self) -> &IndexMessages<u32> {
+            &self.messages
+        }
+        fn messages_mut(&mut self) -> &mut IndexMessages<u32> {
+            &mut self.messages
+        }
+    }
+
+    impl DurableRegistry<String, u32, u32> for TestNode {
+        fn durable(&mut self) -> &mut Durable<String, u32, u32> {
+            &mut self.durable
+        }
+    }
+
+    impl DurableBroadcastHandler<String, u32, u32> for TestNode {}
+
+    #[test]
+    fn test_durable_broadcast_waits_for_acks() {
+        let mut n1 = TestNode::new("n1");
+        let mut n2 = TestNode::new("n2");
+        let mut n3 = TestNode::new("n3");
+        let request = Message {
+            source: "c1".to_owned(),
+            destination: "n1".to_owned(),
+            body: Ok(BroadcastBody::PushRequest {
+                message_id: 7,
+                message: 1000,
+            }),
+        };
+        let pushed = n1.respond_durable_broadcast(&request).unwrap();
+        assert!(pushed.replies.is_empty());
+        assert_eq!(pushed.peers.len(), 2);
+
+        let ack = n2.handle_durable(pushed.peers[0].clone()).peers.remove(0);
+        assert_eq!(*n2.messages().snapshot(), [1000]);
+        assert!(n1.handle_durable(ack.clone()).replies.is_empty());
+        assert!(n1.handle_durable(ack).replies.is_empty());
+
+        let retried = n1.retry_durable();
+        assert_eq!(retried, pushed.peers[1..]);
+        let ack = n3.handle_durable(retried[0].clone()).peers.remove(0);
+        let reply = n1.handle_durable(ack).replies.remove(0);
+        assert_eq!(
+            serde_json::to_string(&reply).unwrap(),
+            r#"{"src":"n1","dest":"c1","body":{"type":"broadcast_ok","in_reply_to":7,"msg_id":3}}"#
+        );
+        assert_eq!(n1.durable.pending(), 0);
+        assert!(n1.retry_durable().is_empty());
+    }
+
+    #[test]
+    fn test_durable_broadcast_replicates_past_quorum() {
+        let mut n1 = TestNode::new("n1");
+        n1.durable = Durable::new(1);
+        let mut n3 = TestNode::new("n3");
+        let request = Message {
+            source: "c1".to_owned(),
+            destination: "n1".to_owned(),
+            body: Ok(BroadcastBody::PushRequest {
+                message_id: 7,
+                message: 1000,
+            }),
+        };
+        let pushed = n1.respond_durable_broadcast(&request).unwrap();
+        let mut n2 = TestNode::new("n2");
+        let ack = n2.handle_durable(pushed.peers[0].clone()).peers.remove(0);
+        assert_eq!(n1.handle_durable(ack).replies.len(), 1);
+
+        // The request to n3 was lost, it is retried even though the client got its reply
+        let retried = n1.retry_durable();
+        assert_eq!(retried, pushed.peers[1..]);
+        let ack = n3.handle_durable(retried[0].clone()).peers.remove(0);
+        assert!(n1.handle_durable(ack).replies.is_empty());
+        assert_eq!(*n3.messages().snapshot(), [1000]);
+        assert_eq!(n1.durable.pending(), 0);
+    }
+}
@@ -0,0 +1,509 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Debug,
+    hash::Hash,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    broadcast::{respond_read, value_hash, BroadcastBody, Broadcasted},
+    error::Code,
+    store::MessageStore,
+    Address, Message, MessageId, MessageIdRegistry, MessageRegistry, NodeIdRegistry,
+    PlumtreeRegistry, TopologyRegistry,
+};
+
+/// Body exchanged between nodes in Plumtree mode
+///
+/// Values are pushed along the tree with `gossip` and announced to the remaining neighbours with
+/// `ihave`. A node missing an announced value asks for it with `graft`, which also adds the link
+/// to the tree and is answered with `gossip`, and `prune` removes a link that delivered a
+/// duplicate. Announcements and grafts only carry the `digest` of a value, a 64 bit hash that is
+/// the same on every node.
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[serde(tag = "type")]
+pub enum PlumtreeBody<I, T>
+where
+    I: MessageId,
+{
+    #[serde(rename = "gossip")]
+    Gossip {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        message: T,
+    },
+    #[serde(rename = "ihave")]
+    IHave {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        digest: u64,
+    },
+    #[serde(rename = "graft")]
+    Graft {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        digest: u64,
+    },
+    #[serde(rename = "prune")]
+    Prune {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+    },
+}
+
+pub type PlumtreeMessage<A, I, T> = Message<A, PlumtreeBody<I, T>, I>;
+pub type PlumtreeBroadcasted<A, I, T> = Broadcasted<A, I, T, PlumtreeBody<I, T>>;
+
+/// Neighbours this node pushes values to, split into eager tree links and lazy links, plus the
+/// digests of the values it spread and of the announced values still missing
+///
+#[derive(Clone, Debug)]
+pub struct Plumtree<A: Address> {
+    eager: HashSet<A>,
+    lazy: HashSet<A>,
+    spread: HashSet<u64>,
+    missing: HashMap<u64, (Instant, VecDeque<A>)>,
+    ihave_timeout: Duration,
+    graft_timeout: Duration,
+}
+
+impl<A: Address> Plumtree<A> {
+    /// A value announced with `ihave` is grafted `ihave_timeout` after the announcement if it
+    /// hasn't arrived, further announcers are tried every `graft_timeout`
+    pub fn new(ihave_timeout: Duration, graft_timeout: Duration) -> Self {
+        Self {
+            eager: HashSet::new(),
+            lazy: HashSet::new(),
+            spread: HashSet::new(),
+            missing: HashMap::new(),
+            ihave_timeout,
+            graft_timeout,
+        }
+    }
+
+    pub fn eager_peers(&self) -> impl Iterator<Item = &A> {
+        self.eager.iter()
+    }
+
+    pub fn lazy_peers(&self) -> impl Iterator<Item = &A> {
+        self.lazy.iter()
+    }
+
+    /// Values announced but not received yet
+    pub fn missing(&self) -> usize {
+        self.missing.len()
+    }
+
+    /// Restarts with every neighbour as an eager link
+    fn seed(&mut self, neighbours: &[A]) {
+        self.lazy.clear();
+        self.eager = neighbours.iter().cloned().collect();
+    }
+
+    fn make_eager(&mut self, peer: &A) {
+        self.lazy.remove(peer);
+        self.eager.insert(peer.clone());
+    }
+
+    fn make_lazy(&mut self, peer: &A) {
+        self.eager.remove(peer);
+        self.lazy.insert(peer.clone());
+    }
+
+    /// Records a value that was delivered and stops waiting for it
+    fn receive(&mut self, digest: u64) {
+        self.missing.remove(&digest);
+        self.spread.insert(digest);
+    }
+
+    /// Waits for a value announced by `from` unless it was received before
+    fn announce(&mut self, digest: u64, from: A, now: Instant) {
+        if self.spread.contains(&digest) {
+            return;
+        }
+        let deadline = now + self.ihave_timeout;
+        let (_, announcers) = self
+            .missing
+            .entry(digest)
+            .or_insert_with(|| (deadline, VecDeque::new()));
+        announcers.push_back(from);
+    }
+
+    /// Takes the digests whose timer expired along with the neighbour to graft them from
+    fn expired(&mut self, now: Instant) -> Vec<(u64, A)> {
+        let mut grafts = Vec::new();
+        for (digest, (deadline, announcers)) in &mut self.missing {
+            if *deadline > now {
+                continue;
+            }
+            if let Some(peer) = announcers.pop_front() {
+                *deadline = now + self.graft_timeout;
+                grafts.push((*digest, peer));
+            }
+        }
+        self.missing
+            .retain(|_, (deadline, announcers)| *deadline > now || !announcers.is_empty());
+        grafts
+    }
+}
+
+/// Broadcast variant spreading values along a Plumtree seeded with the [`TopologyRegistry`]
+/// neighbours
+///
+/// The tree is seeded by [`PlumtreeHandler::seed_plumtree`], which has to be called from
+/// [`crate::topology::TopologyHandler::on_topology`]. Timers aren't driven by the handler,
+/// [`PlumtreeHandler::tick_plumtree`] has to be called periodically to graft missing values.
+///
+pub trait PlumtreeHandler<A, I, T>:
+    NodeIdRegistry<A, I>
+    + MessageIdRegistry<I>
+    + MessageRegistry<T>
+    + TopologyRegistry<A>
+    + PlumtreeRegistry<A>
+where
+    A: Address + DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+    T: Clone + Eq + Hash + DeserializeOwned + Serialize,
+{
+    /// Answers a client request, pushed values are delivered and sent along the tree
+    fn respond_plumtree_broadcast(
+        &mut self,
+        request: BroadcastBody<I, T>,
+    ) -> Result<PlumtreeBroadcasted<A, I, T>, crate::Error<I>> {
+        match request {
+            BroadcastBody::PushRequest {
+                message_id,
+                message,
+            } => {
+                let node = self.node_id().clone();
+                let peers = self.spread(message, &node);
+                let response = BroadcastBody::PushResponse {
+                    in_reply_to: message_id,
+                    message_id: self.gen_msg_id(),
+                };
+                Ok(Broadcasted { response, peers })
+            }
+            BroadcastBody::ReadRequest {
+                message_id,
+                since,
+                limit,
+            } => Ok(Broadcasted {
+                response: respond_read(self, message_id, since, limit)?,
+                peers: Vec::new(),
+            }),
+            BroadcastBody::PushResponse { message_id, .. }
+            | BroadcastBody::ReadResponse { message_id, .. } => Err(crate::Error::new(
+                message_id,
+                Code::MalformedRequest,
+                "Request is response".to_owned(),
+            )),
+        }
+    }
+
+    /// Handles a message from a neighbour, returning the messages it triggers
+    fn handle_plumtree(
+        &mut self,
+        message: PlumtreeMessage<A, I, T>,
+        now: Instant,
+    ) -> Vec<PlumtreeMessage<A, I, T>> {
+        let Ok(body) = message.body else {
+            return Vec::new();
+        };
+        let source = message.source;
+        match body {
+            PlumtreeBody::Gossip { message, .. } => {
+                if self.messages().contains(&message) {
+                    self.plumtree().make_lazy(&source);
+                    let prune = PlumtreeBody::Prune {
+                        message_id: self.gen_msg_id(),
+                    };
+                    return vec![self.plumtree_message(source, prune)];
+                }
+                self.plumtree().make_eager(&source);
+                self.spread(message, &source)
+            }
+            PlumtreeBody::IHave { digest, .. } => {
+                self.plumtree().announce(digest, source, now);
+                Vec::new()
+            }
+            PlumtreeBody::Graft { digest, .. } => {
+                self.plumtree().make_eager(&source);
+                let found = self
+                    .messages()
+                    .iter()
+                    .find(|value| value_hash(*value) == digest)
+                    .cloned();
+                let Some(message) = found else {
+                    return Vec::new();
+                };
+                let gossip = PlumtreeBody::Gossip {
+                    message_id: self.gen_msg_id(),
+                    message,
+                };
+                vec![self.plumtree_message(source, gossip)]
+            }
+            PlumtreeBody::Prune { .. } => {
+                self.plumtree().make_lazy(&source);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Grafts the announced values that didn't arrive in time
+    fn tick_plumtree(&mut self, now: Instant) -> Vec<PlumtreeMessage<A, I, T>> {
+        let mut outgoing = Vec::new();
+        for (digest, peer) in self.plumtree().expired(now) {
+            self.plumtree().make_eager(&peer);
+            let graft = PlumtreeBody::Graft {
+                message_id: self.gen_msg_id(),
+                digest,
+            };
+            outgoing.push(self.plumtree_message(peer, graft));
+        }
+        outgoing
+    }
+
+    /// Delivers a new value and pushes it to the eager links while announcing it to the lazy ones,
+    /// except for the neighbour it came from
+    fn spread(&mut self, value: T, from: &A) -> Vec<PlumtreeMessage<A, I, T>> {
+        let digest = value_hash(&value);
+        self.plumtree().receive(digest);
+        if !self.push_msg(value.clone()) {
+            return Vec::new();
+        }
+        let eager: Vec<A> = self.plumtree().eager_peers().cloned().collect();
+        let lazy: Vec<A> = self.plumtree().lazy_peers().cloned().collect();
+        let mut outgoing = Vec::new();
+        for peer in eager.into_iter().filter(|peer| peer != from) {
+            let gossip = PlumtreeBody::Gossip {
+                message_id: self.gen_msg_id(),
+                message: value.clone(),
+            };
+            outgoing.push(self.plumtree_message(peer, gossip));
+        }
+        for peer in lazy.into_iter().filter(|peer| peer != from) {
+            let ihave = PlumtreeBody::IHave {
+                message_id: self.gen_msg_id(),
+                digest,
+            };
+            outgoing.push(self.plumtree_message(peer, ihave));
+        }
+        outgoing
+    }
+
+    /// Makes every [`TopologyRegistry`] neighbour an eager link, dropping the links learned so far
+    fn seed_plumtree(&mut self) {
+        let neighbours = self.topology().to_vec();
+        self.plumtree().seed(&neighbours);
+    }
+
+    fn plumtree_message(
+        &self,
+        destination: A,
+        body: PlumtreeBody<I, T>,
+    ) -> PlumtreeMessage<A, I, T> {
+        Message {
+            source: self.node_id().clone(),
+            destination,
+            body: Ok(body),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{HashMap, VecDeque},
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        broadcast::{value_hash, BroadcastBody},
+        store::{IndexMessages, MessageStore},
+        topology::{TopologyBody, TopologyHandler},
+        MessageIdRegistry, MessageRegistry, NodeIdRegistry, PlumtreeRegistry, TopologyRegistry,
+    };
+
+    use super::{Plumtree, PlumtreeBody, PlumtreeHandler, PlumtreeMessage};
+
+    pub struct TestNode {
+        n: u32,
+        id: String,
+        topology: Vec<String>,
+        messages: IndexMessages<u32>,
+        plumtree: Plumtree<String>,
+    }
+
+    impl TestNode {
+        fn new(id: &str, topology: &[&str]) -> Self {
+            let mut node = Self {
+                n: 0,
+                id: id.to_owned(),
+                topology: Vec::new(),
+                messages: IndexMessages::default(),
+                plumtree: Plumtree::new(Duration::from_millis(100), Duration::from_millis(50)),
+            };
+            if !topology.is_empty() {
+                node.set_neighbours(topology);
+            }
+            node
+        }
+
+        fn set_neighbours(&mut self, neighbours: &[&str]) {
+            let neighbours = neighbours.iter().map(|peer| peer.to_string()).collect();
+            let request = TopologyBody::Request {
+                message_id: 1,
+                topology: HashMap::from([(self.id.clone(), neighbours)]),
+            };
+            self.respond(request).unwrap();
+        }
+    }
+
+    impl MessageIdRegistry<u32> for TestNode {
+        fn gen_msg_id(&mut self) -> u32 {
+            self.n += 1;
+            self.n
+        }
+    }
+
+    impl NodeIdRegistry<String, u32> for TestNode {
+        fn node_id(&self) -> &String {
+            &self.id
+        }
+
+        fn set_node_id(&mut self, id: String) -> Result<(), crate::Error<u32>> {
+            self.id = id;
+            Ok(())
+        }
+    }
+
+    impl MessageRegistry<u32> for TestNode {
+        type Store = IndexMessages<u32>;
+        fn messages(&self) -> &IndexMessages<u32> {
+            &self.messages
+        }
+        fn messages_mut(&mut self) -> &mut IndexMessages<u32> {
+            &mut self.messages
+        }
+    }
+
+    impl TopologyRegistry<String> for TestNode {
+        fn set_topology(&mut self, topology: Vec<String>) {
+            self.topology = topology;
+        }
+        fn topology(&self) -> &[String] {
+            self.topology.as_slice()
+        }
+    }
+
+    impl PlumtreeRegistry<String> for TestNode {
+        fn plumtree(&mut self) -> &mut Plumtree<String> {
+            &mut self.plumtree
+        }
+    }
+
+    impl PlumtreeHandler<String, u32, u32> for TestNode {}
+    impl TopologyHandler<String, u32, PlumtreeBody<u32, u32>> for TestNode {
+        fn on_topology(&mut self) -> Vec<PlumtreeMessage<String, u32, u32>> {
+            self.seed_plumtree();
+            Vec::new()
+        }
+    }
+
+    /// Delivers messages until none are left and returns how many gossip messages were sent,
+    /// messages `dropped` returns true for are lost
+    fn route(
+        nodes: &mut HashMap<&str, TestNode>,
+        messages: Vec<PlumtreeMessage<String, u32, u32>>,
+        now: Instant,
+        dropped: impl Fn(&PlumtreeMessage<String, u32, u32>) -> bool,
+    ) -> usize {
+        let mut gossip = 0;
+        let mut queue = VecDeque::from(messages);
+        while let Some(message) = queue.pop_front() {
+            if dropped(&message) {
+                continue;
+            }
+            if let Ok(PlumtreeBody::Gossip { .. }) = message.body {
+                gossip += 1;
+            }
+            let node = nodes.get_mut(message.destination.as_str()).unwrap();
+            queue.extend(node.handle_plumtree(message, now));
+        }
+        gossip
+    }
+
+    fn push(
+        nodes: &mut HashMap<&str, TestNode>,
+        node: &str,
+        message: u32,
+    ) -> Vec<PlumtreeMessage<String, u32, u32>> {
+        let request = BroadcastBody::PushRequest {
+            message_id: 1,
+            message,
+        };
+        let node = nodes.get_mut(node).unwrap();
+        node.respond_plumtree_broadcast(request).unwrap().peers
+    }
+
+    #[test]
+    fn test_plumtree_prunes_and_grafts() {
+        let mut nodes = HashMap::from([
+            ("n1", TestNode::new("n1", &["n2", "n3"])),
+            ("n2", TestNode::new("n2", &["n1", "n3"])),
+            ("n3", TestNode::new("n3", &["n1", "n2"])),
+        ]);
+        let now = Instant::now();
+
+        let first = push(&mut nodes, "n1", 1);
+        assert_eq!(route(&mut nodes, first, now, |_| false), 4);
+        let second = push(&mut nodes, "n1", 2);
+        assert_eq!(route(&mut nodes, second, now, |_| false), 2);
+
+        // The tree link to n3 fails, n3 grafts the value announced by n2 once the timer expires
+        let third = push(&mut nodes, "n1", 3);
+        let cut = |message: &PlumtreeMessage<String, u32, u32>| {
+            message.source == "n1" && message.destination == "n3"
+        };
+        route(&mut nodes, third, now, cut);
+        let n3 = nodes.get_mut("n3").unwrap();
+        assert_eq!(n3.plumtree.missing(), 1);
+        assert!(n3.tick_plumtree(now).is_empty());
+        let grafts = n3.tick_plumtree(now + Duration::from_millis(100));
+        assert!(
+            matches!(grafts[0].body, Ok(PlumtreeBody::Graft { digest, .. }) if digest == value_hash(&3))
+        );
+        route(&mut nodes, grafts, now, cut);
+
+        for id in ["n1", "n2", "n3"] {
+            assert_eq!(*nodes[id].messages().snapshot(), [1, 2, 3]);
+        }
+        assert!(nodes["n3"].plumtree.eager_peers().any(|peer| peer == "n2"));
+    }
+
+    #[test]
+    fn test_plumtree_seeds_on_topology() {
+        let mut nodes = HashMap::from([
+            ("n1", TestNode::new("n1", &["n2"])),
+            ("n2", TestNode::new("n2", &[])),
+            ("n3", TestNode::new("n3", &[])),
+        ]);
+        let now = Instant::now();
+
+        // Gossip reaching n2 before its topology only links it to the sender
+        let early = push(&mut nodes, "n1", 1);
+        route(&mut nodes, early, now, |_| false);
+        let n2 = nodes.get_mut("n2").unwrap();
+        assert_eq!(*n2.messages().snapshot(), [1]);
+        assert!(n2.plumtree.eager_peers().eq(["n1"].iter()));
+
+        n2.set_neighbours(&["n1", "n3"]);
+        assert_eq!(n2.plumtree.eager_peers().count(), 2);
+        let second = push(&mut nodes, "n2", 2);
+        assert_eq!(route(&mut nodes, second, now, |_| false), 2);
+        assert_eq!(*nodes["n1"].messages().snapshot(), [1, 2]);
+        assert_eq!(*nodes["n3"].messages().snapshot(), [2]);
+    }
+}
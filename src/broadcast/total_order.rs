@@ -0,0 +1,1105 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Debug,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    broadcast::{respond_read, BroadcastBody},
+    error::Code,
+    reply::ReplyHandle,
+    Address, ClusterRegistry, Message, MessageId, MessageIdRegistry, MessageRegistry,
+    TotalOrderRegistry,
+};
+
+/// Value pushed by a client at `origin`
+///
+/// `incarnation` tells apart the runs of `origin` and `origin_seq` the values pushed during a
+/// run, so a value pushed after a restart is never taken for one sequenced before it.
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
+pub struct Entry<A, T> {
+    pub origin: A,
+    pub incarnation: u64,
+    pub origin_seq: u64,
+    pub message: T,
+}
+
+/// Entry stored in the log, `epoch` is the epoch whose sequencer proposed it last
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
+pub struct Slot<A, T> {
+    pub epoch: u64,
+    pub entry: Entry<A, T>,
+}
+
+/// Body exchanged between nodes in total order broadcast mode
+///
+/// `epoch` selects the sequencer. Requests from an older epoch are only acknowledged, the reply
+/// carries the newer epoch so the sender moves on to its sequencer.
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(bound(
+    serialize = "A: Serialize, I: Serialize, T: Serialize",
+    deserialize = "A: DeserializeOwned, I: DeserializeOwned, T: DeserializeOwned"
+))]
+pub enum TotalOrderBody<A, I, T>
+where
+    A: Address,
+    I: MessageId,
+{
+    #[serde(rename = "order")]
+    OrderRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        epoch: u64,
+        entry: Entry<A, T>,
+    },
+    #[serde(rename = "order_ok")]
+    OrderResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        epoch: u64,
+    },
+    #[serde(rename = "sequenced")]
+    SequencedRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        epoch: u64,
+        seq: u64,
+        entry: Entry<A, T>,
+    },
+    #[serde(rename = "sequenced_ok")]
+    SequencedResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        epoch: u64,
+        seq: u64,
+    },
+    #[serde(rename = "commit")]
+    CommitRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        epoch: u64,
+        committed: u64,
+    },
+    #[serde(rename = "commit_ok")]
+    CommitResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        epoch: u64,
+        delivered: u64,
+    },
+    #[serde(rename = "sync")]
+    SyncRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        epoch: u64,
+        from: u64,
+    },
+    #[serde(rename = "sync_ok")]
+    SyncResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        epoch: u64,
+        delivered: u64,
+        entries: Vec<(u64, Slot<A, T>)>,
+    },
+}
+
+impl<A: Address, I: MessageId, T> TotalOrderBody<A, I, T> {
+    fn epoch(&self) -> u64 {
+        match self {
+            Self::OrderRequest { epoch, .. }
+            | Self::OrderResponse { epoch, .. }
+            | Self::SequencedRequest { epoch, .. }
+            | Self::SequencedResponse { epoch, .. }
+            | Self::CommitRequest { epoch, .. }
+            | Self::CommitResponse { epoch, .. }
+            | Self::SyncRequest { epoch, .. }
+            | Self::SyncResponse { epoch, .. } => *epoch,
+        }
+    }
+}
+
+pub type BroadcastMessage<A, I, T> = Message<A, BroadcastBody<I, T>, I>;
+pub type TotalOrderMessage<A, I, T> = Message<A, TotalOrderBody<A, I, T>, I>;
+
+/// Messages to send after a [`TotalOrderBroadcastHandler`] step
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ordered<A, I, T>
+where
+    A: Address + DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+    T: DeserializeOwned + Serialize,
+{
+    /// Replies to clients whose values were delivered
+    pub replies: Vec<BroadcastMessage<A, I, T>>,
+    pub peers: Vec<TotalOrderMessage<A, I, T>>,
+}
+
+impl<A, I, T> Default for Ordered<A, I, T>
+where
+    A: Address + DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+    T: DeserializeOwned + Serialize,
+{
+    fn default() -> Self {
+        Self {
+            replies: Vec::new(),
+            peers: Vec::new(),
+        }
+    }
+}
+
+/// Sync replies gathered by a node taking over as sequencer
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Takeover<A: Address, T> {
+    from: u64,
+    replied: HashSet<A>,
+    low: u64,
+    high: u64,
+    held: Vec<Entry<A, T>>,
+}
+
+/// Sequenced log of a node plus the values it pushed that haven't been delivered yet
+///
+/// Entries are delivered strictly in sequence order once the sequencer saw a quorum store them.
+/// Entries that weren't delivered survive an epoch change until the new sequencer proposes
+/// something else in their place, values dropped that way are submitted again by their origins.
+/// Clients are answered once their value is delivered at the node they pushed it to.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TotalOrder<A: Address, I: MessageId, T> {
+    incarnation: u64,
+    epoch: u64,
+    submitted: u64,
+    pending: Vec<Entry<A, T>>,
+    replies: HashMap<u64, ReplyHandle<A, I>>,
+    ready: Vec<ReplyHandle<A, I>>,
+    log: BTreeMap<u64, Slot<A, T>>,
+    sequenced: HashMap<(A, u64, u64), u64>,
+    acks: BTreeMap<u64, HashSet<A>>,
+    committed: u64,
+    delivered: u64,
+    takeover: Option<Takeover<A, T>>,
+    suspect_timeout: Duration,
+    progress: Option<(u64, Instant)>,
+}
+
+impl<A: Address, I: MessageId, T: Clone> TotalOrder<A, I, T> {
+    /// `incarnation` has to differ between runs of the node, e.g. the epoch persisted by
+    /// [`crate::id::Durable`]. The sequencer is suspected once values pushed here saw no delivery
+    /// for `suspect_timeout`
+    pub fn new(incarnation: u64, suspect_timeout: Duration) -> Self {
+        Self {
+            incarnation,
+            epoch: 0,
+            submitted: 0,
+            pending: Vec::new(),
+            replies: HashMap::new(),
+            ready: Vec::new(),
+            log: BTreeMap::new(),
+            sequenced: HashMap::new(),
+            acks: BTreeMap::new(),
+            committed: 0,
+            delivered: 0,
+            takeover: None,
+            suspect_timeout,
+            progress: None,
+        }
+    }
+
+    pub fn incarnation(&self) -> u64 {
+        self.incarnation
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Number of entries known to be stored at a quorum
+    pub fn committed(&self) -> u64 {
+        self.committed
+    }
+
+    /// Number of entries delivered, i.e. the sequence number of the next one
+    pub fn delivered(&self) -> u64 {
+        self.delivered
+    }
+
+    /// Values pushed at this node that haven't been delivered yet
+    pub fn pending(&self) -> &[Entry<A, T>] {
+        &self.pending
+    }
+
+    /// Whether this node is gathering the log before sequencing in the current epoch
+    pub fn is_taking_over(&self) -> bool {
+        self.takeover.is_some()
+    }
+
+    fn submit(&mut self, origin: A, message: T, reply: ReplyHandle<A, I>) -> Entry<A, T> {
+        self.submitted += 1;
+        let entry = Entry {
+            origin,
+            incarnation: self.incarnation,
+            origin_seq: self.submitted,
+            message,
+        };
+        self.pending.push(entry.clone());
+        self.replies.insert(self.submitted, reply);
+        entry
+    }
+
+    /// Replies to values delivered since the last call
+    fn take_ready(&mut self) -> Vec<ReplyHandle<A, I>> {
+        std::mem::take(&mut self.ready)
+    }
+
+    /// Whether values pushed here saw no delivery for `suspect_timeout`, the wait starts over
+    /// once it returns true
+    fn stalled(&mut self, now: Instant) -> bool {
+        if self.pending.is_empty() {
+            self.progress = None;
+            return false;
+        }
+        match self.progress {
+            Some((delivered, since)) if delivered == self.delivered => {
+                if now.saturating_duration_since(since) < self.suspect_timeout {
+                    return false;
+                }
+                self.progress = Some((self.delivered, now));
+                true
+            }
+            _ => {
+                self.progress = Some((self.delivered, now));
+                false
+            }
+        }
+    }
+
+    fn advance(&mut self, epoch: u64) -> bool {
+        if epoch <= self.epoch {
+            return false;
+        }
+        self.epoch = epoch;
+        self.takeover = None;
+        self.acks.clear();
+        true
+    }
+
+    /// Appends `entry` to the log, `None` if it was sequenced before
+    fn sequence(&mut self, entry: Entry<A, T>) -> Option<u64> {
+        let seq = self.log.last_key_value().map_or(0, |(seq, _)| seq + 1);
+        let slot = Slot {
+            epoch: self.epoch,
+            entry,
+        };
+        (!self.sequenced.contains_key(&id(&slot.entry)) && self.insert(seq, slot)).then_some(seq)
+    }
+
+    /// Stores `slot` unless an entry of the same or a newer epoch is in the way, returns whether
+    /// the log holds `slot` afterwards
+    fn insert(&mut self, seq: u64, slot: Slot<A, T>) -> bool {
+        if seq < self.delivered {
+            return true;
+        }
+        if let Some(current) = self.log.get(&seq) {
+            if current.epoch >= slot.epoch {
+                return current.epoch == slot.epoch;
+            }
+        }
+        if let Some(&other) = self.sequenced.get(&id(&slot.entry)) {
+            match self.log.get(&other) {
+                Some(_) if other < self.delivered => return false,
+                Some(current) if current.epoch >= slot.epoch => return false,
+                _ => self.remove(other),
+            }
+        }
+        self.remove(seq);
+        self.sequenced.insert(id(&slot.entry), seq);
+        self.log.insert(seq, slot);
+        true
+    }
+
+    fn remove(&mut self, seq: u64) {
+        if let Some(slot) = self.log.remove(&seq) {
+            let id = id(&slot.entry);
+            if self.sequenced.get(&id) == Some(&seq) {
+                self.sequenced.remove(&id);
+            }
+        }
+    }
+
+    /// Keeps the contiguous part of the log gathered by a takeover as proposals of the current
+    /// epoch, entries up to `committed` were delivered somewhere
+    fn resume(&mut self, committed: u64) {
+        let mut end = self.delivered;
+        while self.log.contains_key(&end) {
+            end += 1;
+        }
+        let dropped: Vec<u64> = self.log.range(end..).map(|(seq, _)| *seq).collect();
+        for seq in dropped {
+            self.remove(seq);
+        }
+        for slot in self.log.range_mut(self.delivered..).map(|(_, slot)| slot) {
+            slot.epoch = self.epoch;
+        }
+        self.committed = self.committed.max(committed.min(end));
+    }
+
+    fn acknowledge(&mut self, seq: u64, peer: A) {
+        if seq >= self.committed {
+            self.acks.entry(seq).or_default().insert(peer);
+        }
+    }
+
+    /// Commits the entries of the current epoch stored at `quorum` nodes counting this one,
+    /// returns whether any were committed
+    fn commit(&mut self, quorum: usize) -> bool {
+        let start = self.committed;
+        while let Some(slot) = self.log.get(&self.committed) {
+            let acks = self.acks.get(&self.committed).map_or(0, HashSet::len);
+            if slot.epoch != self.epoch || acks + 1 < quorum {
+                break;
+            }
+            self.acks.remove(&self.committed);
+            self.committed += 1;
+        }
+        self.committed > start
+    }
+
+    fn set_committed(&mut self, committed: u64) {
+        self.committed = self.committed.max(committed);
+    }
+
+    /// Takes the committed values that can be delivered in sequence order, entries from an older
+    /// epoch wait for the current sequencer to propose them again
+    fn deliver(&mut self, node: &A) -> Vec<T> {
+        let mut values = Vec::new();
+        while self.delivered < self.committed {
+            let Some(slot) = self.log.get(&self.delivered) else {
+                break;
+            };
+            if slot.epoch != self.epoch {
+                break;
+            }
+            if slot.entry.origin == *node && slot.entry.incarnation == self.incarnation {
+                let origin_seq = slot.entry.origin_seq;
+                self.pending
+                    .retain(|pending| pending.origin_seq != origin_seq);
+                self.ready.extend(self.replies.remove(&origin_seq));
+            }
+            values.push(slot.entry.message.clone());
+            self.delivered += 1;
+        }
+        values
+    }
+
+    /// Every stored entry from `from` on, including those that weren't delivered
+    fn entries(&self, from: u64) -> Vec<(u64, Slot<A, T>)> {
+        self.log
+            .range(from..)
+            .map(|(seq, slot)| (*seq, slot.clone()))
+            .collect()
+    }
+}
+
+fn id<A: Address, T>(entry: &Entry<A, T>) -> (A, u64, u64) {
+    (entry.origin.clone(), entry.incarnation, entry.origin_seq)
+}
+
+/// Broadcast variant delivering values to the [`MessageRegistry`] in the same order on every node
+///
+/// Values are routed to a sequencer which assigns them consecutive sequence numbers and commits
+/// them once a quorum stored them, only committed values are delivered. The sequencer of an epoch
+/// is picked round robin from the init membership, when it is suspected to have failed the next
+/// epoch's sequencer first gathers the stored log from a quorum and proposes it again before
+/// sequencing. Every committed entry is stored at one of the nodes in that quorum, so the order
+/// survives failovers and partitions.
+///
+/// [`TotalOrderBroadcastHandler::tick_total_order`] has to be called periodically, it resends
+/// what got lost and suspects the sequencer when values pushed here stop being delivered.
+///
+pub trait TotalOrderBroadcastHandler<A, I, T>:
+    ClusterRegistry<A, I> + MessageIdRegistry<I> + MessageRegistry<T> + TotalOrderRegistry<A, I, T>
+where
+    A: Address + DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+    T: Clone + DeserializeOwned + Serialize,
+{
+    /// Sequencer of the current epoch
+    fn sequencer(&mut self) -> Option<A> {
+        let epoch = self.total_order().epoch();
+        let node_ids = self.node_ids();
+        let index = epoch.checked_rem(node_ids.len() as u64)?;
+        node_ids.get(index as usize).cloned()
+    }
+
+    /// Whether this node sequences the current epoch and finished taking over
+    fn is_sequencing(&mut self) -> bool {
+        let node = self.node_id().clone();
+        self.sequencer() == Some(node) && !self.total_order().is_taking_over()
+    }
+
+    /// Answers a client request, pushed values are sent to the sequencer and answered once they
+    /// are delivered here
+    fn respond_total_order_broadcast(
+        &mut self,
+        request: &BroadcastMessage<A, I, T>,
+    ) -> Result<Ordered<A, I, T>, crate::Error<I>> {
+        let Some(reply) = ReplyHandle::new(request) else {
+            return Ok(Ordered::default());
+        };
+        match &request.body {
+            Ok(BroadcastBody::PushRequest { message, .. }) => {
+                let node = self.node_id().clone();
+                let entry = self.total_order().submit(node, message.clone(), reply);
+                let peers = self.order(entry);
+                Ok(self.ordered(peers))
+            }
+            Ok(BroadcastBody::ReadRequest {
+                message_id,
+                since,
+                limit,
+            }) => {
+                let response = respond_read(self, message_id.clone(), *since, *limit);
+                Ok(Ordered {
+                    replies: vec![reply.complete(response)],
+                    peers: Vec::new(),
+                })
+            }
+            Ok(_) | Err(_) => Err(crate::Error::new(
+                reply.in_reply_to().clone(),
+                Code::MalformedRequest,
+                "Request is response".to_owned(),
+            )),
+        }
+    }
+
+    /// Handles a message from a peer, answering the clients whose values it delivered
+    fn handle_total_order(&mut self, message: TotalOrderMessage<A, I, T>) -> Ordered<A, I, T> {
+        let peers = self.process_total_order(message);
+        self.ordered(peers)
+    }
+
+    /// Retries what this node is waiting on, or moves on to the next sequencer once values pushed
+    /// here saw no delivery for the suspect timeout
+    fn tick_total_order(&mut self, now: Instant) -> Ordered<A, I, T> {
+        let peers = if self.total_order().stalled(now) {
+            self.suspect_sequencer()
+        } else {
+            self.retry_total_order()
+        };
+        self.ordered(peers)
+    }
+
+    /// Resends the pending values to the sequencer. A node taking over resends the sync requests
+    /// that weren't answered, the sequencer resends the uncommitted log and the commit point
+    fn retry_total_order(&mut self) -> Vec<TotalOrderMessage<A, I, T>> {
+        if let Some(takeover) = &self.total_order().takeover {
+            let from = takeover.from;
+            let replied = takeover.replied.clone();
+            let peers = self.peers();
+            let missing = peers
+                .into_iter()
+                .filter(|peer| !replied.contains(peer))
+                .collect();
+            return self.request_sync(from, missing);
+        }
+        let pending = self.total_order().pending().to_vec();
+        let mut outgoing: Vec<_> = pending
+            .into_iter()
+            .flat_map(|entry| self.order(entry))
+            .collect();
+        if self.is_sequencing() {
+            let committed = self.total_order().committed();
+            let peers = self.peers();
+            outgoing.extend(self.disseminate(committed, peers));
+            outgoing.extend(self.announce_commit());
+        }
+        outgoing
+    }
+
+    /// Handles a message from a peer, returning the reply followed by any messages it triggers
+    fn process_total_order(
+        &mut self,
+        message: TotalOrderMessage<A, I, T>,
+    ) -> Vec<TotalOrderMessage<A, I, T>> {
+        let Message {
+            source,
+            destination,
+            body: Ok(body),
+        } = message
+        else {
+            return Vec::new();
+        };
+        let mut outgoing = match body.epoch() {
+            epoch if epoch > self.total_order().epoch() => self.change_epoch(epoch),
+            _ => Vec::new(),
+        };
+        let current = self.total_order().epoch();
+        let reply = match body {
+            TotalOrderBody::OrderRequest {
+                message_id,
+                epoch,
+                entry,
+            } => {
+                if epoch == current {
+                    outgoing.extend(self.order(entry));
+                }
+                TotalOrderBody::OrderResponse {
+                    in_reply_to: message_id,
+                    message_id: self.gen_msg_id(),
+                    epoch: current,
+                }
+            }
+            TotalOrderBody::SequencedRequest {
+                message_id,
+                epoch,
+                seq,
+                entry,
+            } => {
+                if epoch == current {
+                    self.total_order().insert(seq, Slot { epoch, entry });
+                    self.deliver_total_order();
+                }
+                TotalOrderBody::SequencedResponse {
+                    in_reply_to: message_id,
+                    message_id: self.gen_msg_id(),
+                    epoch: current,
+                    seq,
+                }
+            }
+            TotalOrderBody::CommitRequest {
+                message_id,
+                epoch,
+                committed,
+            } => {
+                if epoch == current {
+                    self.total_order().set_committed(committed);
+                    self.deliver_total_order();
+                }
+                TotalOrderBody::CommitResponse {
+                    in_reply_to: message_id,
+                    message_id: self.gen_msg_id(),
+                    epoch: current,
+                    delivered: self.total_order().delivered(),
+                }
+            }
+            TotalOrderBody::SyncRequest {
+                message_id, from, ..
+            } => TotalOrderBody::SyncResponse {
+                in_reply_to: message_id,
+                message_id: self.gen_msg_id(),
+                epoch: current,
+                delivered: self.total_order().delivered(),
+                entries: self.total_order().entries(from),
+            },
+            TotalOrderBody::SyncResponse {
+                epoch,
+                delivered,
+                entries,
+                ..
+            } => {
+                if epoch == current {
+                    outgoing.extend(self.complete_sync(source, delivered, entries));
+                }
+                return outgoing;
+            }
+            TotalOrderBody::SequencedResponse { epoch, seq, .. } => {
+                if epoch == current && self.is_sequencing() {
+                    self.total_order().acknowledge(seq, source);
+                    outgoing.extend(self.commit_total_order());
+                }
+                return outgoing;
+            }
+            TotalOrderBody::CommitResponse {
+                epoch, delivered, ..
+            } => {
+                // The peer missed entries, e.g. while it was partitioned, and gets them again
+                if epoch == current
+                    && self.is_sequencing()
+                    && delivered < self.total_order().committed()
+                {
+                    outgoing.extend(self.disseminate(delivered, vec![source]));
+                }
+                return outgoing;
+            }
+            TotalOrderBody::OrderResponse { .. } => return outgoing,
+        };
+        outgoing.insert(
+            0,
+            Message {
+                source: destination,
+                destination: source,
+                body: Ok(reply),
+            },
+        );
+        outgoing
+    }
+
+    /// Moves on to the next epoch's sequencer, for when the current one is suspected to have failed
+    fn suspect_sequencer(&mut self) -> Vec<TotalOrderMessage<A, I, T>> {
+        let epoch = self.total_order().epoch() + 1;
+        self.change_epoch(epoch)
+    }
+
+    /// Enters `epoch`, either starting the takeover or resubmitting pending values to its sequencer
+    fn change_epoch(&mut self, epoch: u64) -> Vec<TotalOrderMessage<A, I, T>> {
+        if !self.total_order().advance(epoch) {
+            return Vec::new();
+        }
+        let node = self.node_id().clone();
+        if self.sequencer().as_ref() != Some(&node) {
+            let pending = self.total_order().pending().to_vec();
+            return pending
+                .into_iter()
+                .flat_map(|entry| self.order(entry))
+                .collect();
+        }
+        let from = self.total_order().delivered();
+        self.total_order().takeover = Some(Takeover {
+            from,
+            replied: HashSet::new(),
+            low: from,
+            high: from,
+            held: Vec::new(),
+        });
+        let mut outgoing = self.complete_sync(node, from, Vec::new());
+        let peers = self.peers();
+        outgoing.extend(self.request_sync(from, peers));
+        outgoing
+    }
+
+    /// Asks `destinations` for their log from `from` on
+    fn request_sync(&mut self, from: u64, destinations: Vec<A>) -> Vec<TotalOrderMessage<A, I, T>> {
+        let node = self.node_id().clone();
+        let epoch = self.total_order().epoch();
+        destinations
+            .into_iter()
+            .map(|destination| Message {
+                source: node.clone(),
+                destination,
+                body: Ok(TotalOrderBody::SyncRequest {
+                    message_id: self.gen_msg_id(),
+                    epoch,
+                    from,
+                }),
+            })
+            .collect()
+    }
+
+    /// Merges the log of a sync reply, once a quorum replied the gathered log is proposed to every
+    /// peer and sequencing resumes
+    fn complete_sync(
+        &mut self,
+        source: A,
+        delivered: u64,
+        entries: Vec<(u64, Slot<A, T>)>,
+    ) -> Vec<TotalOrderMessage<A, I, T>> {
+        let quorum = self.quorum();
+        let state = self.total_order();
+        let Some(takeover) = &mut state.takeover else {
+            // Late reply to a finished takeover, only the replying node needs catching up
+            return self.disseminate(delivered, vec![source]);
+        };
+        takeover.replied.insert(source);
+        takeover.low = takeover.low.min(delivered);
+        takeover.high = takeover.high.max(delivered);
+        let replied = takeover.replied.len();
+        for (seq, slot) in entries {
+            state.insert(seq, slot);
+        }
+        if replied < quorum {
+            return Vec::new();
+        }
+        let Some(takeover) = state.takeover.take() else {
+            return Vec::new();
+        };
+        state.resume(takeover.high);
+        self.deliver_total_order();
+        let peers = self.peers();
+        let mut outgoing = self.disseminate(takeover.low, peers);
+        outgoing.extend(self.commit_total_order());
+        let pending = self.total_order().pending().to_vec();
+        for entry in takeover.held.into_iter().chain(pending) {
+            outgoing.extend(self.order(entry));
+        }
+        outgoing
+    }
+
+    /// Sends `entry` to the sequencer, or sequences it when this node is the sequencer
+    fn order(&mut self, entry: Entry<A, T>) -> Vec<TotalOrderMessage<A, I, T>> {
+        let Some(sequencer) = self.sequencer() else {
+            return Vec::new();
+        };
+        let node = self.node_id().clone();
+        if sequencer != node {
+            return vec![Message {
+                source: node,
+                destination: sequencer,
+                body: Ok(TotalOrderBody::OrderRequest {
+                    message_id: self.gen_msg_id(),
+                    epoch: self.total_order().epoch(),
+                    entry,
+                }),
+            }];
+        }
+        if let Some(takeover) = &mut self.total_order().takeover {
+            takeover.held.push(entry);
+            return Vec::new();
+        }
+        let Some(seq) = self.total_order().sequence(entry) else {
+            return Vec::new();
+        };
+        let peers = self.peers();
+        let mut outgoing = self.disseminate(seq, peers);
+        outgoing.extend(self.commit_total_order());
+        outgoing
+    }
+
+    /// Proposes the log from `from` on to `destinations` in the current epoch
+    fn disseminate(&mut self, from: u64, destinations: Vec<A>) -> Vec<TotalOrderMessage<A, I, T>> {
+        let node = self.node_id().clone();
+        let epoch = self.total_order().epoch();
+        let entries = self.total_order().entries(from);
+        let mut outgoing = Vec::new();
+        for destination in destinations {
+            for (seq, slot) in &entries {
+                outgoing.push(Message {
+                    source: node.clone(),
+                    destination: destination.clone(),
+                    body: Ok(TotalOrderBody::SequencedRequest {
+                        message_id: self.gen_msg_id(),
+                        epoch,
+                        seq: *seq,
+                        entry: slot.entry.clone(),
+                    }),
+                });
+            }
+        }
+        outgoing
+    }
+
+    /// Delivers newly committed entries and tells every peer about them
+    fn commit_total_order(&mut self) -> Vec<TotalOrderMessage<A, I, T>> {
+        let quorum = self.quorum();
+        if !self.total_order().commit(quorum) {
+            return Vec::new();
+        }
+        self.deliver_total_order();
+        self.announce_commit()
+    }
+
+    /// Tells every peer how far the log is committed
+    fn announce_commit(&mut self) -> Vec<TotalOrderMessage<A, I, T>> {
+        let node = self.node_id().clone();
+        let epoch = self.total_order().epoch();
+        let committed = self.total_order().committed();
+        let mut outgoing = Vec::new();
+        for peer in self.peers() {
+            outgoing.push(Message {
+                source: node.clone(),
+                destination: peer,
+                body: Ok(TotalOrderBody::CommitRequest {
+                    message_id: self.gen_msg_id(),
+                    epoch,
+                    committed,
+                }),
+            });
+        }
+        outgoing
+    }
+
+    fn deliver_total_order(&mut self) {
+        let node = self.node_id().clone();
+        for value in self.total_order().deliver(&node) {
+            self.push_msg(value);
+        }
+    }
+
+    /// Pairs `peers` with the replies to the clients whose values were delivered meanwhile
+    fn ordered(&mut self, peers: Vec<TotalOrderMessage<A, I, T>>) -> Ordered<A, I, T> {
+        let replies = self
+            .total_order()
+            .take_ready()
+            .into_iter()
+            .map(|reply| self.push_reply(reply))
+            .collect();
+        Ordered { replies, peers }
+    }
+
+    fn push_reply(&mut self, reply: ReplyHandle<A, I>) -> BroadcastMessage<A, I, T> {
+        let body = BroadcastBody::PushResponse {
+            in_reply_to: reply.in_reply_to().clone(),
+            message_id: self.gen_msg_id(),
+        };
+        reply.complete(Ok(body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::{HashMap, VecDeque},
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        broadcast::BroadcastBody,
+        store::{IndexMessages, MessageStore},
+        ClusterRegistry, Message, MessageIdRegistry, MessageRegistry, NodeIdRegistry,
+        TotalOrderRegistry,
+    };
+
+    use super::{BroadcastMessage, TotalOrder, TotalOrderBroadcastHandler, TotalOrderMessage};
+
+    const SUSPECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+    pub struct TestNode {
+        n: u32,
+        id: String,
+        node_ids: Vec<String>,
+        messages: IndexMessages<u32>,
+        total_order: TotalOrder<String, u32, u32>,
+    }
+
+    impl TestNode {
+        fn new(id: &str, incarnation: u64) -> Self {
+            Self {
+                n: 0,
+                id: id.to_owned(),
+                node_ids: vec!["n1".to_owned(), "n2".to_owned(), "n3".to_owned()],
+                messages: IndexMessages::default(),
+                total_order: TotalOrder::new(incarnation, SUSPECT_TIMEOUT),
+            }
+        }
+    }
+
+    impl MessageIdRegistry<u32> for TestNode {
+        fn gen_msg_id(&mut self) -> u32 {
+            self.n += 1;
+            self.n
+        }
+    }
+
+    impl NodeIdRegistry<String, u32> for TestNode {
+        fn node_id(&self) -> &String {
+            &self.id
+        }
+
+        fn set_node_id(&mut self, id: String) -> Result<(), crate::Error<u32>> {
+            self.id = id;
+            Ok(())
+        }
+    }
+
+    impl ClusterRegistry<String, u32> for TestNode {
+        fn set_node_ids(&mut self, node_ids: Vec<String>) {
+            self.node_ids = node_ids;
+        }
+
+        fn node_ids(&self) -> &[String] {
+            &self.node_ids
+        }
+    }
+
+    impl MessageRegistry<u32> for TestNode {
+        type Store = IndexMessages<u32>;
+        fn messages(&self) -> &IndexMessages<u32> {
+            &self.messages
+        }
+        fn messages_mut(&mut self) -> &mut IndexMessages<u32> {
+            &mut self.messages
+        }
+    }
+
+    impl TotalOrderRegistry<String, u32, u32> for TestNode {
+        fn total_order(&mut self) -> &mut TotalOrder<String, u32, u32> {
+            &mut self.total_order
+        }
+    }
+
+    impl TotalOrderBroadcastHandler<String, u32, u32> for TestNode {}
+
+    fn cluster() -> HashMap<&'static str, TestNode> {
+        ["n1", "n2", "n3"]
+            .into_iter()
+            .map(|id| (id, TestNode::new(id, 0)))
+            .collect()
+    }
+
+    /// Delivers messages until none are left, dropping those addressed to crashed nodes and those
+    /// `cut` returns true for, returns the replies to clients
+    fn route(
+        nodes: &mut HashMap<&str, TestNode>,
+        messages: Vec<TotalOrderMessage<String, u32, u32>>,
+        cut: impl Fn(&TotalOrderMessage<String, u32, u32>) -> bool,
+    ) -> Vec<BroadcastMessage<String, u32, u32>> {
+        let mut queue = VecDeque::from(messages);
+        let mut replies = Vec::new();
+        while let Some(message) = queue.pop_back() {
+            if cut(&message) {
+                continue;
+            }
+            if let Some(node) = nodes.get_mut(message.destination.as_str()) {
+                let ordered = node.handle_total_order(message);
+                replies.extend(ordered.replies);
+                queue.extend(ordered.peers);
+            }
+        }
+        replies
+    }
+
+    fn push(
+        nodes: &mut HashMap<&str, TestNode>,
+        node: &str,
+        message: u32,
+    ) -> Vec<TotalOrderMessage<String, u32, u32>> {
+        let request = Message {
+            source: "c1".to_owned(),
+            destination: node.to_owned(),
+            body: Ok(BroadcastBody::PushRequest {
+                message_id: message,
+                message,
+            }),
+        };
+        let node = nodes.get_mut(node).unwrap();
+        let ordered = node.respond_total_order_broadcast(&request).unwrap();
+        assert!(ordered.replies.is_empty());
+        ordered.peers
+    }
+
+    fn answered(replies: &[BroadcastMessage<String, u32, u32>]) -> Vec<u32> {
+        replies
+            .iter()
+            .filter_map(|reply| match reply.body {
+                Ok(BroadcastBody::PushResponse { in_reply_to, .. }) => Some(in_reply_to),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_total_order_survives_sequencer_failover() {
+        let mut nodes = cluster();
+
+        let from_n2 = push(&mut nodes, "n2", 1);
+        let from_n3 = push(&mut nodes, "n3", 2);
+        route(&mut nodes, from_n3, |_| false);
+        route(&mut nodes, from_n2, |_| false);
+        for id in ["n1", "n2", "n3"] {
+            assert_eq!(*nodes[id].messages().snapshot(), [2, 1]);
+        }
+
+        // n1 crashes with a value pushed at n3 still on its way
+        nodes.remove("n1");
+        let lost = push(&mut nodes, "n3", 3);
+        route(&mut nodes, lost, |_| false);
+        let takeover = nodes.get_mut("n2").unwrap().suspect_sequencer();
+        route(&mut nodes, takeover, |_| false);
+        let from_n2 = push(&mut nodes, "n2", 4);
+        route(&mut nodes, from_n2, |_| false);
+
+        for id in ["n2", "n3"] {
+            assert_eq!(*nodes[id].messages().snapshot(), [2, 1, 3, 4]);
+            assert!(nodes[id].total_order.pending().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_total_order_heals_partitioned_sequencer() {
+        let mut nodes = cluster();
+        let from_n2 = push(&mut nodes, "n2", 1);
+        route(&mut nodes, from_n2, |_| false);
+
+        // n1 keeps sequencing on its side of the partition but never reaches a quorum
+        let partition = |message: &TotalOrderMessage<String, u32, u32>| {
+            message.source == "n1" || message.destination == "n1"
+        };
+        let isolated = push(&mut nodes, "n1", 2);
+        route(&mut nodes, isolated, partition);
+        assert_eq!(*nodes["n1"].messages().snapshot(), [1]);
+
+        let takeover = nodes.get_mut("n2").unwrap().suspect_sequencer();
+        route(&mut nodes, takeover, partition);
+        let from_n3 = push(&mut nodes, "n3", 3);
+        route(&mut nodes, from_n3, partition);
+        for id in ["n2", "n3"] {
+            assert_eq!(*nodes[id].messages().snapshot(), [1, 3]);
+        }
+
+        // Once healed n1 learns the epoch from the next proposal, catches up and resubmits
+        let from_n2 = push(&mut nodes, "n2", 4);
+        route(&mut nodes, from_n2, |_| false);
+        for id in ["n1", "n2", "n3"] {
+            assert_eq!(*nodes[id].messages().snapshot(), [1, 3, 4, 2]);
+            assert!(nodes[id].total_order.pending().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_total_order_replies_once_delivered() {
+        let mut nodes = cluster();
+        let from_n2 = push(&mut nodes, "n2", 1);
+        let replies = route(&mut nodes, from_n2, |_| false);
+        assert_eq!(answered(&replies), [1]);
+        assert_eq!(replies[0].destination, "c1");
+        assert_eq!(replies[0].source, "n2");
+    }
+
+    #[test]
+    fn test_total_order_retries_and_suspects_sequencer() {
+        let mut nodes = cluster();
+        let start = Instant::now();
+
+        // The order request is lost, the retry reaches n1
+        let lost = push(&mut nodes, "n3", 1);
+        assert_eq!(lost.len(), 1);
+        let retry = nodes.get_mut("n3").unwrap().tick_total_order(start);
+        assert_eq!(retry.peers.len(), 1);
+        assert_eq!(retry.peers[0].destination, "n1");
+        let replies = route(&mut nodes, retry.peers, |_| false);
+        assert_eq!(answered(&replies), [1]);
+
+        // Once n1 crashed nothing gets delivered until n3 suspects it
+        nodes.remove("n1");
+        let from_n3 = push(&mut nodes, "n3", 2);
+        route(&mut nodes, from_n3, |_| false);
+        let retry = nodes.get_mut("n3").unwrap().tick_total_order(start);
+        assert!(route(&mut nodes, retry.peers, |_| false).is_empty());
+        let takeover = nodes
+            .get_mut("n3")
+            .unwrap()
+            .tick_total_order(start + SUSPECT_TIMEOUT);
+        let replies = route(&mut nodes, takeover.peers, |_| false);
+        assert_eq!(answered(&replies), [2]);
+        for id in ["n2", "n3"] {
+            assert_eq!(*nodes[id].messages().snapshot(), [1, 2]);
+        }
+    }
+
+    #[test]
+    fn test_total_order_tells_restarts_apart() {
+        let mut nodes = cluster();
+        let from_n3 = push(&mut nodes, "n3", 1);
+        route(&mut nodes, from_n3, |_| false);
+
+        // The restarted n3 numbers its values from scratch again
+        nodes.insert("n3", TestNode::new("n3", 1));
+        let from_n3 = push(&mut nodes, "n3", 2);
+        let replies = route(&mut nodes, from_n3, |_| false);
+        assert_eq!(answered(&replies), [2]);
+        for id in ["n1", "n2", "n3"] {
+            assert_eq!(*nodes[id].messages().snapshot(), [1, 2]);
+        }
+    }
+}
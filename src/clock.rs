@@ -0,0 +1,394 @@
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{runtime::Node, Address, Message, MessageId};
+
+/// Logical clock whose timestamps travel with messages
+///
+pub trait Clock {
+    type Timestamp: Clone + DeserializeOwned + Serialize;
+
+    /// Advances the clock for a local or send event and returns the new timestamp
+    fn tick(&mut self) -> Self::Timestamp;
+
+    /// Advances the clock past a timestamp received from another node
+    fn observe(&mut self, remote: &Self::Timestamp);
+
+    fn now(&self) -> Self::Timestamp;
+}
+
+/// Lamport clock, a single counter consistent with causality
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Lamport(u64);
+
+impl Clock for Lamport {
+    type Timestamp = u64;
+
+    fn tick(&mut self) -> u64 {
+        self.0 += 1;
+        self.0
+    }
+
+    fn observe(&mut self, remote: &u64) {
+        self.0 = self.0.max(*remote) + 1;
+    }
+
+    fn now(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Per node event counters, ordered by happened-before
+///
+/// Two timestamps that are neither before nor after each other are concurrent and compare as
+/// `None`. Nodes without an entry count as zero, also when comparing for equality.
+///
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(bound(serialize = "A: Serialize", deserialize = "A: DeserializeOwned"))]
+pub struct VectorTimestamp<A: Address>(HashMap<A, u64>);
+
+impl<A: Address> Default for VectorTimestamp<A> {
+    fn default() -> Self {
+        Self(HashMap::new())
+    }
+}
+
+impl<A: Address> VectorTimestamp<A> {
+    pub fn get(&self, node: &A) -> u64 {
+        self.0.get(node).copied().unwrap_or(0)
+    }
+
+    pub fn increment(&mut self, node: &A) -> u64 {
+        let entry = self.0.entry(node.clone()).or_insert(0);
+        *entry += 1;
+        *entry
+    }
+
+    /// Pointwise maximum
+    pub fn merge(&mut self, other: &Self) {
+        for (node, count) in &other.0 {
+            let entry = self.0.entry(node.clone()).or_insert(0);
+            *entry = (*entry).max(*count);
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&A, u64)> {
+        self.0.iter().map(|(node, count)| (node, *count))
+    }
+
+    pub fn is_concurrent(&self, other: &Self) -> bool {
+        self.partial_cmp(other).is_none()
+    }
+}
+
+impl<A: Address> PartialEq for VectorTimestamp<A> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0
+            .keys()
+            .chain(other.0.keys())
+            .all(|node| self.get(node) == other.get(node))
+    }
+}
+
+impl<A: Address> Eq for VectorTimestamp<A> {}
+
+impl<A: Address> PartialOrd for VectorTimestamp<A> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let nodes = self.0.keys().chain(other.0.keys());
+        let (mut less, mut greater) = (false, false);
+        for node in nodes {
+            match self.get(node).cmp(&other.get(node)) {
+                Ordering::Less => less = true,
+                Ordering::Greater => greater = true,
+                Ordering::Equal => {}
+            }
+        }
+        match (less, greater) {
+            (false, false) => Some(Ordering::Equal),
+            (true, false) => Some(Ordering::Less),
+            (false, true) => Some(Ordering::Greater),
+            (true, true) => None,
+        }
+    }
+}
+
+/// Vector clock owned by a single node
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VectorClock<A: Address> {
+    node: A,
+    timestamp: VectorTimestamp<A>,
+}
+
+impl<A: Address> VectorClock<A> {
+    pub fn new(node: A) -> Self {
+        Self {
+            node,
+            timestamp: VectorTimestamp::default(),
+        }
+    }
+}
+
+impl<A: Address + DeserializeOwned + Serialize> Clock for VectorClock<A> {
+    type Timestamp = VectorTimestamp<A>;
+
+    fn tick(&mut self) -> VectorTimestamp<A> {
+        self.timestamp.increment(&self.node);
+        self.timestamp.clone()
+    }
+
+    fn observe(&mut self, remote: &VectorTimestamp<A>) {
+        self.timestamp.merge(remote);
+        self.timestamp.increment(&self.node);
+    }
+
+    fn now(&self) -> VectorTimestamp<A> {
+        self.timestamp.clone()
+    }
+}
+
+/// Hybrid logical clock timestamp, wall clock milliseconds refined by a logical counter
+///
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize,
+)]
+pub struct HlcTimestamp {
+    pub wall: u64,
+    pub logical: u32,
+}
+
+/// Hybrid logical clock, stays close to the wall clock without ever going backwards
+///
+#[derive(Clone, Copy, Debug)]
+pub struct HybridLogicalClock {
+    last: HlcTimestamp,
+    wall_clock: fn() -> u64,
+}
+
+impl Default for HybridLogicalClock {
+    fn default() -> Self {
+        Self::with_wall_clock(unix_millis)
+    }
+}
+
+impl HybridLogicalClock {
+    /// Uses `wall_clock` as the source of physical time in milliseconds
+    pub fn with_wall_clock(wall_clock: fn() -> u64) -> Self {
+        Self {
+            last: HlcTimestamp::default(),
+            wall_clock,
+        }
+    }
+}
+
+impl Clock for HybridLogicalClock {
+    type Timestamp = HlcTimestamp;
+
+    fn tick(&mut self) -> HlcTimestamp {
+        let wall = (self.wall_clock)();
+        self.last = if wall > self.last.wall {
+            HlcTimestamp { wall, logical: 0 }
+        } else {
+            HlcTimestamp {
+                wall: self.last.wall,
+                logical: self.last.logical + 1,
+            }
+        };
+        self.last
+    }
+
+    fn observe(&mut self, remote: &HlcTimestamp) {
+        let wall = (self.wall_clock)().max(self.last.wall).max(remote.wall);
+        let logical = match (wall == self.last.wall, wall == remote.wall) {
+            (true, true) => self.last.logical.max(remote.logical) + 1,
+            (true, false) => self.last.logical + 1,
+            (false, true) => remote.logical + 1,
+            (false, false) => 0,
+        };
+        self.last = HlcTimestamp { wall, logical };
+    }
+
+    fn now(&self) -> HlcTimestamp {
+        self.last
+    }
+}
+
+pub(crate) fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Body stamped with the sender's clock, the timestamp is carried in a `clock` field next to the
+/// fields of `body`
+///
+/// Bodies without a `clock` field, e.g. from clients, are accepted as well.
+///
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct Clocked<B, T> {
+    #[serde(flatten)]
+    pub body: B,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock: Option<T>,
+}
+
+/// Runs a node on unstamped bodies while stamping outgoing messages and merging the clocks of
+/// incoming ones
+///
+#[derive(Clone, Debug)]
+pub struct ClockedNode<N, C> {
+    pub node: N,
+    pub clock: C,
+}
+
+impl<A, B, I, N, C> Node<A, Clocked<B, C::Timestamp>, I> for ClockedNode<N, C>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+    N: Node<A, B, I>,
+    C: Clock,
+{
+    fn handle(
+        &mut self,
+        message: Message<A, Clocked<B, C::Timestamp>, I>,
+    ) -> Vec<Message<A, Clocked<B, C::Timestamp>, I>> {
+        let body = message.body.map(|clocked| {
+            match &clocked.clock {
+                Some(remote) => self.clock.observe(remote),
+                None => {
+                    self.clock.tick();
+                }
+            }
+            clocked.body
+        });
+        let outgoing = self.node.handle(Message {
+            source: message.source,
+            destination: message.destination,
+            body,
+        });
+        self.stamp(outgoing)
+    }
+
+    fn on_shutdown(&mut self) -> Vec<Message<A, Clocked<B, C::Timestamp>, I>> {
+        let outgoing = self.node.on_shutdown();
+        self.stamp(outgoing)
+    }
+}
+
+impl<N, C: Clock> ClockedNode<N, C> {
+    fn stamp<A, B, I>(
+        &mut self,
+        outgoing: Vec<Message<A, B, I>>,
+    ) -> Vec<Message<A, Clocked<B, C::Timestamp>, I>>
+    where
+        A: Address,
+        B: DeserializeOwned + Serialize,
+        I: MessageId + DeserializeOwned + Serialize,
+    {
+        outgoing
+            .into_iter()
+            .map(|message| Message {
+                source: message.source,
+                destination: message.destination,
+                body: message.body.map(|body| Clocked {
+                    body,
+                    clock: Some(self.clock.tick()),
+                }),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp::Ordering;
+
+    use crate::{echo::EchoBody, runtime::Node, Message};
+
+    use super::{
+        Clock, Clocked, ClockedNode, HlcTimestamp, HybridLogicalClock, Lamport, VectorClock,
+        VectorTimestamp,
+    };
+
+    #[test]
+    fn test_clocks() {
+        let mut lamport = Lamport::default();
+        lamport.tick();
+        lamport.observe(&7);
+        assert_eq!(lamport.now(), 8);
+
+        let mut n1 = VectorClock::new("n1".to_owned());
+        let mut n2 = VectorClock::new("n2".to_owned());
+        let sent = n1.tick();
+        let concurrent = n2.tick();
+        assert!(sent.is_concurrent(&concurrent));
+        n2.observe(&sent);
+        assert_eq!(sent.partial_cmp(&n2.now()), Some(Ordering::Less));
+
+        let zero: VectorTimestamp<String> = serde_json::from_str(r#"{"n1":0}"#).unwrap();
+        let empty = VectorTimestamp::default();
+        assert_eq!(zero.partial_cmp(&empty), Some(Ordering::Equal));
+        assert_eq!(zero, empty);
+        assert_ne!(zero, sent);
+
+        let mut hlc = HybridLogicalClock::with_wall_clock(|| 100);
+        assert_eq!(
+            hlc.tick(),
+            HlcTimestamp {
+                wall: 100,
+                logical: 0
+            }
+        );
+        hlc.observe(&HlcTimestamp {
+            wall: 150,
+            logical: 3,
+        });
+        assert_eq!(
+            hlc.tick(),
+            HlcTimestamp {
+                wall: 150,
+                logical: 5
+            }
+        );
+    }
+
+    #[test]
+    fn test_clocked_node() {
+        let request =
+            r#"{"src":"n2","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"hi","clock":41}}"#;
+        let request: Message<String, Clocked<EchoBody<u32>, u64>, u32> =
+            serde_json::from_str(request).unwrap();
+        let mut node = ClockedNode {
+            node: |request: Message<String, EchoBody<u32>, u32>| {
+                let Ok(EchoBody::Request { message_id, echo }) = request.body else {
+                    return Vec::new();
+                };
+                vec![Message {
+                    source: request.destination,
+                    destination: request.source,
+                    body: Ok(EchoBody::Response {
+                        in_reply_to: message_id,
+                        message_id: 1,
+                        echo,
+                    }),
+                }]
+            },
+            clock: Lamport::default(),
+        };
+        let response = node.handle(request);
+        let expected = r#"{"src":"n1","dest":"n2","body":{"type":"echo_ok","in_reply_to":1,"msg_id":1,"echo":"hi","clock":43}}"#;
+        assert_eq!(serde_json::to_string(&response[0]).unwrap(), expected);
+
+        let unstamped = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":2,"echo":"hi"}}"#;
+        let unstamped: Message<String, Clocked<EchoBody<u32>, u64>, u32> =
+            serde_json::from_str(unstamped).unwrap();
+        assert_eq!(unstamped.body.unwrap().clock, None);
+    }
+}
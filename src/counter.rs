@@ -0,0 +1,67 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt::Debug, hash::Hash};
+
+use crate::MessageId;
+
+/// Body for the `g-counter` and `pn-counter` workloads, `pn-counter` allows negative deltas
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[serde(tag = "type")]
+pub enum CounterBody<I>
+where
+    I: MessageId,
+{
+    #[serde(rename = "add")]
+    AddRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        delta: i64,
+    },
+    #[serde(rename = "add_ok")]
+    AddResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+    },
+    #[serde(rename = "read")]
+    ReadRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+    },
+    #[serde(rename = "read_ok")]
+    ReadResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        value: i64,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use crate::Message;
+
+    use super::CounterBody;
+
+    #[test]
+    fn test_parse_counter_add() {
+        let request = r#"{
+          "src": "c1",
+          "dest": "n1",
+          "body": {
+            "type": "add",
+            "msg_id": 3,
+            "delta": -4
+          }
+        } "#;
+        let request: Message<String, CounterBody<u32>, u32> =
+            serde_json::from_str(request).unwrap();
+        assert_eq!(
+            request.body,
+            Ok(CounterBody::AddRequest {
+                message_id: 3,
+                delta: -4
+            })
+        );
+    }
+}
@@ -1,7 +1,7 @@
 use serde::{Deserialize, Serialize};
 use std::{fmt::Debug, hash::Hash};
 
-use crate::{error::Code, Address, MessageId, MessageIndex};
+use crate::{error::Code, Address, MessageId, MessageIdRegistry};
 
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
 #[serde(tag = "type")]
@@ -23,7 +23,7 @@ pub enum EchoBody<I> {
 
 /// This trait has to be implement for every Node alongside any workload specific functionality
 ///
-pub trait EchoHandler<A: Address, I: MessageIndex>: MessageId<I> {
+pub trait EchoHandler<A: Address, I: MessageId>: MessageIdRegistry<I> {
     fn respond_echo(&mut self, request: EchoBody<I>) -> Result<EchoBody<I>, crate::Error<I>> {
         match request {
             EchoBody::Request { message_id, echo } => Ok(EchoBody::Response {
@@ -42,7 +42,7 @@ pub trait EchoHandler<A: Address, I: MessageIndex>: MessageId<I> {
 
 #[cfg(test)]
 mod test {
-    use crate::{Message, MessageId, ResponseBuilder};
+    use crate::{Message, MessageIdRegistry, ResponseBuilder};
 
     use super::{EchoBody, EchoHandler};
 
@@ -51,7 +51,7 @@ mod test {
         n: u32,
     }
 
-    impl MessageId<u32> for TestNode {
+    impl MessageIdRegistry<u32> for TestNode {
         fn gen_msg_id(&mut self) -> u32 {
             self.n += 1;
             self.n
@@ -1,33 +1,248 @@
-use crate::MessageIndex;
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt,
+    hash::{Hash, Hasher},
+    io,
+    sync::{OnceLock, RwLock},
+};
+
+use crate::MessageId;
 use derive_new::new;
 use serde::{Deserialize, Serialize};
-use serde_repr::{Deserialize_repr, Serialize_repr};
 
-#[derive(Debug, Serialize_repr, Deserialize_repr, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
-#[repr(u32)]
+/// Error codes defined by the Maelstrom protocol
+///
+/// Codes without a dedicated variant deserialize into `Other`, workloads can attach a name and
+/// definiteness to their own codes with [`Code::register`]. Codes compare by their numeric value,
+/// so `Other(11)` is the same code as `TemporarilyUnavailable`.
+///
+#[derive(Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(from = "u32", into = "u32")]
 pub enum Code {
-    Timeout = 0,
-    NodeNotFound = 1,
-    NotSupported = 10,
-    TemporarilyUnavailable = 11,
-    MalformedRequest = 12,
-    Crash = 13,
-    Abort = 14,
-    KeyDoesNotExist = 20,
-    KeyAlreadyExists = 21,
-    PreconditionFailed = 22,
-    TxnConflict = 30,
+    Timeout,
+    NodeNotFound,
+    NotSupported,
+    TemporarilyUnavailable,
+    MalformedRequest,
+    Crash,
+    Abort,
+    KeyDoesNotExist,
+    KeyAlreadyExists,
+    PreconditionFailed,
+    TxnConflict,
+    Other(u32),
+}
+
+impl From<u32> for Code {
+    fn from(code: u32) -> Self {
+        match code {
+            0 => Code::Timeout,
+            1 => Code::NodeNotFound,
+            10 => Code::NotSupported,
+            11 => Code::TemporarilyUnavailable,
+            12 => Code::MalformedRequest,
+            13 => Code::Crash,
+            14 => Code::Abort,
+            20 => Code::KeyDoesNotExist,
+            21 => Code::KeyAlreadyExists,
+            22 => Code::PreconditionFailed,
+            30 => Code::TxnConflict,
+            other => Code::Other(other),
+        }
+    }
+}
+
+impl From<Code> for u32 {
+    fn from(code: Code) -> Self {
+        match code {
+            Code::Timeout => 0,
+            Code::NodeNotFound => 1,
+            Code::NotSupported => 10,
+            Code::TemporarilyUnavailable => 11,
+            Code::MalformedRequest => 12,
+            Code::Crash => 13,
+            Code::Abort => 14,
+            Code::KeyDoesNotExist => 20,
+            Code::KeyAlreadyExists => 21,
+            Code::PreconditionFailed => 22,
+            Code::TxnConflict => 30,
+            Code::Other(other) => other,
+        }
+    }
+}
+
+impl PartialEq for Code {
+    fn eq(&self, other: &Self) -> bool {
+        u32::from(*self) == u32::from(*other)
+    }
+}
+
+impl Eq for Code {}
+
+impl Hash for Code {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        u32::from(*self).hash(state);
+    }
+}
+
+impl PartialOrd for Code {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Code {
+    fn cmp(&self, other: &Self) -> Ordering {
+        u32::from(*self).cmp(&u32::from(*other))
+    }
+}
+
+/// Name and definiteness of a user defined code
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomCode {
+    pub name: &'static str,
+    pub definite: bool,
+}
+
+fn custom_codes() -> &'static RwLock<HashMap<u32, CustomCode>> {
+    static CODES: OnceLock<RwLock<HashMap<u32, CustomCode>>> = OnceLock::new();
+    CODES.get_or_init(Default::default)
+}
+
+impl Code {
+    /// First code Maelstrom leaves free for workload specific errors
+    pub const CUSTOM_START: u32 = 1000;
+
+    /// Registers a process wide name and definiteness for a custom code, returning the previous
+    /// registration if there was one
+    ///
+    /// # Panics
+    ///
+    /// Panics if `code` is below [`Code::CUSTOM_START`], those are reserved by Maelstrom.
+    pub fn register(code: u32, name: &'static str, definite: bool) -> Option<CustomCode> {
+        assert!(
+            code >= Self::CUSTOM_START,
+            "error code {code} is reserved by Maelstrom"
+        );
+        custom_codes()
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .insert(code, CustomCode { name, definite })
+    }
+
+    /// The registration of an `Other` code, if any
+    pub fn custom(&self) -> Option<CustomCode> {
+        match self.canonical() {
+            Code::Other(code) => custom_codes()
+                .read()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .get(&code)
+                .copied(),
+            _ => None,
+        }
+    }
+
+    /// The dedicated variant for codes built as `Other`
+    fn canonical(self) -> Self {
+        Code::from(u32::from(self))
+    }
+
+    /// Whether the operation is known to not have taken place
+    ///
+    /// Indefinite errors such as `Timeout` and `Crash` leave the outcome unknown, so the operation
+    /// may or may not have been applied. Unregistered custom codes are assumed to be indefinite.
+    pub fn is_definite(&self) -> bool {
+        match self.canonical() {
+            Code::Timeout | Code::Crash => false,
+            Code::Other(_) => self.custom().is_some_and(|custom| custom.definite),
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.canonical() {
+            Code::Timeout => "timeout",
+            Code::NodeNotFound => "node-not-found",
+            Code::NotSupported => "not-supported",
+            Code::TemporarilyUnavailable => "temporarily-unavailable",
+            Code::MalformedRequest => "malformed-request",
+            Code::Crash => "crash",
+            Code::Abort => "abort",
+            Code::KeyDoesNotExist => "key-does-not-exist",
+            Code::KeyAlreadyExists => "key-already-exists",
+            Code::PreconditionFailed => "precondition-failed",
+            Code::TxnConflict => "txn-conflict",
+            Code::Other(code) => {
+                return match self.custom() {
+                    Some(custom) => f.write_str(custom.name),
+                    None => write!(f, "error-{code}"),
+                }
+            }
+        };
+        f.write_str(name)
+    }
+}
+
+/// Failing to read or write the stream is a crash, anything else means the input was invalid
+impl From<&serde_json::Error> for Code {
+    fn from(err: &serde_json::Error) -> Self {
+        match err.classify() {
+            serde_json::error::Category::Io => Code::Crash,
+            _ => Code::MalformedRequest,
+        }
+    }
+}
+
+impl From<&io::Error> for Code {
+    fn from(err: &io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput => Code::MalformedRequest,
+            _ => Code::Crash,
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug, Deserialize, Serialize, Eq, PartialEq, Hash, Clone, new)]
 #[serde(tag = "type", rename = "error")]
-pub struct Error<I: MessageIndex> {
+#[error("{code}: {msg}")]
+pub struct Error<I: MessageId> {
     in_reply_to: I,
     code: Code,
     #[serde(rename = "text")]
     msg: String,
 }
 
+impl<I: MessageId> Error<I> {
+    /// Builds a reply to `in_reply_to` from any error that maps onto a protocol code
+    pub fn caused_by<E>(in_reply_to: I, cause: &E) -> Self
+    where
+        for<'a> &'a E: Into<Code>,
+        E: fmt::Display,
+    {
+        Self::new(in_reply_to, cause.into(), cause.to_string())
+    }
+
+    pub fn in_reply_to(&self) -> &I {
+        &self.in_reply_to
+    }
+
+    pub fn code(&self) -> Code {
+        self.code
+    }
+
+    pub fn msg(&self) -> &str {
+        &self.msg
+    }
+
+    pub fn is_definite(&self) -> bool {
+        self.code.is_definite()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -59,4 +274,41 @@ mod test {
         let serialized = serde_json::to_string(&error);
         assert_eq!(serialized.unwrap(), expected);
     }
+
+    #[test]
+    fn test_error_classification() {
+        assert!(!Code::Timeout.is_definite());
+        assert!(!Code::Crash.is_definite());
+        assert!(Code::Abort.is_definite());
+        assert!(Code::TxnConflict.is_definite());
+
+        let cause = serde_json::from_str::<u32>("{").unwrap_err();
+        let error = Error::caused_by(5u32, &cause);
+        assert_eq!(error.code(), Code::MalformedRequest);
+        assert_eq!(error.in_reply_to(), &5);
+        assert!(error.to_string().starts_with("malformed-request: "));
+
+        let cause = io::Error::new(io::ErrorKind::BrokenPipe, "stdout closed");
+        let error = Error::caused_by(5u32, &cause);
+        assert_eq!(error.code(), Code::Crash);
+        assert!(!error.is_definite());
+    }
+
+    #[test]
+    fn test_custom_error_code() {
+        let json = r#"{"type":"error","in_reply_to":5,"code":1005,"text":"insufficient funds"}"#;
+        let parsed: Error<u32> = serde_json::from_str(json).unwrap();
+        assert_eq!(parsed.code(), Code::Other(1005));
+        assert!(!parsed.is_definite());
+        assert_eq!(parsed.code().to_string(), "error-1005");
+
+        Code::register(1005, "insufficient-funds", true);
+        assert!(parsed.is_definite());
+        assert_eq!(parsed.code().to_string(), "insufficient-funds");
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), json);
+        assert_eq!(Code::from(11), Code::TemporarilyUnavailable);
+        assert_eq!(Code::Other(11), Code::TemporarilyUnavailable);
+        assert_eq!(Code::Other(11).to_string(), "temporarily-unavailable");
+        assert!(Code::Other(0) < Code::NodeNotFound);
+    }
 }
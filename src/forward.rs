@@ -0,0 +1,196 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{error::Code, reply::ReplyHandle, Address, Message, MessageId};
+
+/// Proxies requests to another node and relays the replies back to the original sender
+///
+/// On the way out a request is re-addressed from this node to the target and given a fresh
+/// `msg_id`, on the way back the reply is re-addressed to the original sender and its
+/// `in_reply_to` restored to the sender's `msg_id`. Requests the target doesn't answer within
+/// `timeout` are failed by [`Forwarder::expire`].
+///
+#[derive(Clone, Debug)]
+pub struct Forwarder<A: Address, I: MessageId> {
+    timeout: Duration,
+    pending: HashMap<(A, I), (ReplyHandle<A, I>, Instant)>,
+}
+
+impl<A: Address, I: MessageId> Default for Forwarder<A, I> {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(5))
+    }
+}
+
+impl<A: Address, I: MessageId> Forwarder<A, I> {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<A, I> Forwarder<A, I>
+where
+    A: Address,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    /// Builds the request for `target`, hands `request` back if it carries no `msg_id` to answer
+    pub fn forward<B>(
+        &mut self,
+        request: Message<A, B, I>,
+        target: A,
+        message_id: I,
+        now: Instant,
+    ) -> Result<Message<A, B, I>, Message<A, B, I>>
+    where
+        B: DeserializeOwned + Serialize,
+    {
+        let Some(reply) = ReplyHandle::new(&request) else {
+            return Err(request);
+        };
+        let mut forwarded = Message {
+            source: request.destination,
+            destination: target.clone(),
+            body: request.body,
+        };
+        if !forwarded.set_body_field("msg_id", &message_id) {
+            return Err(Message {
+                source: request.source,
+                destination: forwarded.source,
+                body: forwarded.body,
+            });
+        }
+        self.pending.insert((target, message_id), (reply, now));
+        Ok(forwarded)
+    }
+
+    /// Builds the reply to the original sender if `reply` answers a forwarded request, otherwise
+    /// hands `reply` back
+    ///
+    /// The relayed reply gets `message_id` as its own `msg_id` when it carries one, since ids
+    /// assigned by the target are not unique among the messages sent by this node.
+    pub fn relay<B>(
+        &mut self,
+        reply: Message<A, B, I>,
+        message_id: I,
+    ) -> Result<Message<A, B, I>, Message<A, B, I>>
+    where
+        B: DeserializeOwned + Serialize,
+    {
+        let handle = reply
+            .body_field::<I>("in_reply_to")
+            .and_then(|id| self.pending.remove(&(reply.source.clone(), id)));
+        let Some((handle, _)) = handle else {
+            return Err(reply);
+        };
+        let in_reply_to = handle.in_reply_to().clone();
+        let mut relayed = handle.complete(reply.body);
+        relayed.set_body_field("in_reply_to", in_reply_to);
+        relayed.set_body_field("msg_id", message_id);
+        Ok(relayed)
+    }
+
+    /// Drops the forwarded requests older than the timeout, returning `Timeout` errors for their
+    /// original senders
+    pub fn expire<B>(&mut self, now: Instant) -> Vec<Message<A, B, I>>
+    where
+        B: DeserializeOwned + Serialize,
+    {
+        let timeout = self.timeout;
+        let expired: Vec<(A, I)> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, sent))| now.saturating_duration_since(*sent) >= timeout)
+            .map(|(key, _)| key.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|key| self.pending.remove(&key))
+            .map(|(reply, _)| {
+                reply.fail(
+                    Code::Timeout,
+                    "Forwarded request was not answered".to_owned(),
+                )
+            })
+            .collect()
+    }
+
+    /// Number of forwarded requests still waiting for a reply
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{error::Code, kafka::KafkaBody, Message};
+
+    use super::Forwarder;
+
+    #[test]
+    fn test_forward_and_relay() {
+        let request = r#"{
+          "src": "c1",
+          "dest": "n2",
+          "body": {
+            "type": "send",
+            "msg_id": 3,
+            "key": "k1",
+            "msg": 7
+          }
+        } "#;
+        let request: Message<String, KafkaBody<u32, u64>, u32> =
+            serde_json::from_str(request).unwrap();
+        let mut forwarder = Forwarder::default();
+        let now = Instant::now();
+        let forwarded = forwarder
+            .forward(request.clone(), "n1".to_owned(), 40, now)
+            .unwrap();
+        let expected =
+            r#"{"src":"n2","dest":"n1","body":{"type":"send","msg_id":40,"key":"k1","msg":7}}"#;
+        assert_eq!(serde_json::to_string(&forwarded).unwrap(), expected);
+
+        let reply = r#"{
+          "src": "n1",
+          "dest": "n2",
+          "body": {
+            "type": "send_ok",
+            "in_reply_to": 40,
+            "msg_id": 12,
+            "offset": 1000
+          }
+        } "#;
+        let reply: Message<String, KafkaBody<u32, u64>, u32> = serde_json::from_str(reply).unwrap();
+        let relayed = forwarder.relay(reply.clone(), 41).unwrap();
+        let expected = r#"{"src":"n2","dest":"c1","body":{"type":"send_ok","in_reply_to":3,"msg_id":41,"offset":1000}}"#;
+        assert_eq!(serde_json::to_string(&relayed).unwrap(), expected);
+        assert_eq!(forwarder.relay(reply.clone(), 42), Err(reply));
+        assert_eq!(forwarder.pending(), 0);
+
+        forwarder
+            .forward(request.clone(), "n1".to_owned(), 43, now)
+            .unwrap();
+        assert!(forwarder
+            .expire::<KafkaBody<u32, u64>>(now + Duration::from_secs(1))
+            .is_empty());
+        let expired: Vec<Message<String, KafkaBody<u32, u64>, u32>> =
+            forwarder.expire(now + Duration::from_secs(5));
+        assert_eq!(expired[0].destination, "c1");
+        let err = expired[0].body.as_ref().unwrap_err();
+        assert_eq!((err.code(), *err.in_reply_to()), (Code::Timeout, 3));
+        assert_eq!(forwarder.pending(), 0);
+
+        let mut notification = request;
+        notification.body = Err(crate::Error::new(3, Code::Abort, "Aborted".to_owned()));
+        let returned = forwarder.forward(notification.clone(), "n1".to_owned(), 44, now);
+        assert_eq!(returned, Err(notification));
+    }
+}
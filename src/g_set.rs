@@ -0,0 +1,237 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{fmt::Debug, hash::Hash, sync::Arc};
+
+use crate::{
+    error::Code, store::MessageStore, Address, ClusterRegistry, GSetRegistry, Message, MessageId,
+    MessageIdRegistry,
+};
+
+/// Body for the `g-set` workload
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[serde(tag = "type")]
+pub enum GSetBody<I, T>
+where
+    I: MessageId,
+{
+    #[serde(rename = "add")]
+    AddRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        element: T,
+    },
+    #[serde(rename = "add_ok")]
+    AddResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+    },
+    #[serde(rename = "read")]
+    ReadRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+    },
+    #[serde(rename = "read_ok")]
+    ReadResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        value: Arc<[T]>,
+    },
+}
+
+/// Body exchanged between nodes to converge their sets
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[serde(tag = "type")]
+pub enum GSetStateBody<I, T>
+where
+    I: MessageId,
+{
+    #[serde(rename = "g_set_state")]
+    State {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        elements: Arc<[T]>,
+    },
+}
+
+pub type GSetStateMessage<A, I, T> = Message<A, GSetStateBody<I, T>, I>;
+
+/// Handles the `g-set` workload
+///
+/// Elements are added to the [`GSetRegistry`] store and reach the other nodes through
+/// [`GSetHandler::gossip_g_set`]. Sets only grow, so merging the state of a peer is a union and
+/// reads are eventually consistent.
+///
+pub trait GSetHandler<A, I, T>:
+    ClusterRegistry<A, I> + MessageIdRegistry<I> + GSetRegistry<T>
+where
+    A: Address + DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+    T: Clone + DeserializeOwned + Serialize,
+{
+    fn respond_g_set(
+        &mut self,
+        request: GSetBody<I, T>,
+    ) -> Result<GSetBody<I, T>, crate::Error<I>> {
+        match request {
+            GSetBody::AddRequest {
+                message_id,
+                element,
+            } => {
+                self.g_set_mut().insert(element);
+                Ok(GSetBody::AddResponse {
+                    in_reply_to: message_id,
+                    message_id: self.gen_msg_id(),
+                })
+            }
+            GSetBody::ReadRequest { message_id } => Ok(GSetBody::ReadResponse {
+                in_reply_to: message_id,
+                message_id: self.gen_msg_id(),
+                value: self.g_set().snapshot(),
+            }),
+            GSetBody::AddResponse { message_id, .. }
+            | GSetBody::ReadResponse { message_id, .. } => Err(crate::Error::new(
+                message_id,
+                Code::MalformedRequest,
+                "Request is response".to_owned(),
+            )),
+        }
+    }
+
+    /// Sends every element to every peer, to be called periodically
+    fn gossip_g_set(&mut self) -> Vec<GSetStateMessage<A, I, T>> {
+        let elements = self.g_set().snapshot();
+        let source = self.node_id().clone();
+        let mut outgoing = Vec::new();
+        for peer in self.peers() {
+            outgoing.push(Message {
+                source: source.clone(),
+                destination: peer,
+                body: Ok(GSetStateBody::State {
+                    message_id: self.gen_msg_id(),
+                    elements: elements.clone(),
+                }),
+            });
+        }
+        outgoing
+    }
+
+    fn handle_g_set(&mut self, body: GSetStateBody<I, T>) {
+        match body {
+            GSetStateBody::State { elements, .. } => {
+                for element in elements.iter() {
+                    self.g_set_mut().insert(element.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        store::{BTreeMessages, MessageStore},
+        ClusterRegistry, GSetRegistry, Message, MessageIdRegistry, NodeIdRegistry, ResponseBuilder,
+    };
+
+    use super::{GSetBody, GSetHandler};
+
+    pub struct TestNode {
+        n: u32,
+        id: String,
+        node_ids: Vec<String>,
+        elements: BTreeMessages<u32>,
+    }
+
+    impl TestNode {
+        fn new(id: &str) -> Self {
+            Self {
+                n: 0,
+                id: id.to_owned(),
+                node_ids: vec!["n1".to_owned(), "n2".to_owned()],
+                elements: BTreeMessages::default(),
+            }
+        }
+    }
+
+    impl MessageIdRegistry<u32> for TestNode {
+        fn gen_msg_id(&mut self) -> u32 {
+            self.n += 1;
+            self.n
+        }
+    }
+
+    impl NodeIdRegistry<String, u32> for TestNode {
+        fn node_id(&self) -> &String {
+            &self.id
+        }
+
+        fn set_node_id(&mut self, id: String) -> Result<(), crate::Error<u32>> {
+            self.id = id;
+            Ok(())
+        }
+    }
+
+    impl ClusterRegistry<String, u32> for TestNode {
+        fn set_node_ids(&mut self, node_ids: Vec<String>) {
+            self.node_ids = node_ids;
+        }
+
+        fn node_ids(&self) -> &[String] {
+            &self.node_ids
+        }
+    }
+
+    impl GSetRegistry<u32> for TestNode {
+        type Store = BTreeMessages<u32>;
+        fn g_set(&self) -> &BTreeMessages<u32> {
+            &self.elements
+        }
+        fn g_set_mut(&mut self) -> &mut BTreeMessages<u32> {
+            &mut self.elements
+        }
+    }
+
+    impl GSetHandler<String, u32, u32> for TestNode {}
+    impl ResponseBuilder<String, u32, GSetBody<u32, u32>> for TestNode {}
+
+    #[test]
+    fn test_g_set_converges() {
+        let mut n1 = TestNode::new("n1");
+        let mut n2 = TestNode::new("n2");
+        let add = r#"{
+          "src": "c1",
+          "dest": "n1",
+          "body": {
+            "type": "add",
+            "msg_id": 1,
+            "element": 3
+          }
+        } "#;
+        let add: Message<String, GSetBody<u32, u32>, u32> = serde_json::from_str(add).unwrap();
+        let response = add.body.clone().and_then(|body| n1.respond_g_set(body));
+        let response = serde_json::to_string(&TestNode::build_response(&add, response)).unwrap();
+        assert_eq!(
+            response,
+            r#"{"src":"n1","dest":"c1","body":{"type":"add_ok","in_reply_to":1,"msg_id":1}}"#
+        );
+        n2.respond_g_set(GSetBody::AddRequest {
+            message_id: 1,
+            element: 1,
+        })
+        .unwrap();
+
+        for message in n1.gossip_g_set() {
+            n2.handle_g_set(message.body.unwrap());
+        }
+        for message in n2.gossip_g_set() {
+            n1.handle_g_set(message.body.unwrap());
+        }
+        assert_eq!(*n1.g_set().snapshot(), [1, 3]);
+        assert_eq!(*n2.g_set().snapshot(), [1, 3]);
+        let read = n2.respond_g_set(GSetBody::ReadRequest { message_id: 2 });
+        assert!(matches!(read, Ok(GSetBody::ReadResponse { value, .. }) if *value == [1, 3]));
+    }
+}
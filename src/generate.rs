@@ -1,13 +1,19 @@
 use serde::{Deserialize, Serialize};
 use std::{fmt::Debug, hash::Hash};
 
-use crate::{error::Code, Address, MessageIndex};
+use crate::{
+    error::Code, id::IdGenerator, Address, IdGeneratorRegistry, MessageId, MessageIdRegistry,
+    NodeIdRegistry,
+};
 
+/// Body for the `generate` workload, ids are strings unless a numeric scheme such as
+/// `lease::BlockLease` is used
+///
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
 #[serde(tag = "type")]
-pub enum GenerateBody<I>
+pub enum GenerateBody<I, T = String>
 where
-    I: MessageIndex,
+    I: MessageId,
 {
     #[serde(rename = "generate")]
     Request {
@@ -19,16 +25,17 @@ where
         in_reply_to: I,
         #[serde(rename = "msg_id")]
         message_id: I,
-        id: String,
+        id: T,
     },
 }
 
 /// This trait has to be implement for every Node alongside any workload specific functionality
 ///
-pub trait GenerateHandler<A: Address, I: MessageIndex>: NodeId<A, I> + MessageId<I>
+pub trait GenerateHandler<A: Address, I: MessageId>:
+    NodeIdRegistry<A, I> + MessageIdRegistry<I> + IdGeneratorRegistry<A, I>
 where
     A: Address,
-    I: MessageIndex,
+    I: MessageId,
 {
     fn respond_generate(
         &mut self,
@@ -37,11 +44,11 @@ where
         match request {
             GenerateBody::Request { message_id } => {
                 let new_message_id = self.gen_msg_id();
-                let id = format!(
-                    "{}-{}",
-                    self.node_id().to_string(),
-                    new_message_id.to_string()
-                );
+                let node_id = self.node_id().clone();
+                let id = self
+                    .id_generator()
+                    .next_id(&node_id, &new_message_id)
+                    .map_err(|err| crate::Error::caused_by(message_id.clone(), &err))?;
                 Ok(GenerateBody::Response {
                     in_reply_to: message_id,
                     id,
@@ -59,7 +66,10 @@ where
 
 #[cfg(test)]
 mod test {
-    use crate::{Message, MessageIdRegistry, NodeIdRegistry, ResponseBuilder};
+    use crate::{
+        id::Sequential, IdGeneratorRegistry, Message, MessageIdRegistry, NodeIdRegistry,
+        ResponseBuilder,
+    };
 
     use super::{GenerateBody, GenerateHandler};
 
@@ -67,6 +77,7 @@ mod test {
     pub struct TestNode {
         n: u32,
         id: String,
+        ids: Sequential,
     }
 
     impl MessageIdRegistry<u32> for TestNode {
@@ -87,6 +98,14 @@ mod test {
         }
     }
 
+    impl IdGeneratorRegistry<String, u32> for TestNode {
+        type Generator = Sequential;
+
+        fn id_generator(&mut self) -> &mut Sequential {
+            &mut self.ids
+        }
+    }
+
     impl GenerateHandler<String, u32> for TestNode {}
     impl ResponseBuilder<String, u32, GenerateBody<u32>> for TestNode {}
 
@@ -104,6 +123,7 @@ mod test {
         let mut test_node = TestNode {
             n: 0,
             id: "n2".to_owned(),
+            ids: Sequential,
         };
         let expected = r#"{"src":"n1","dest":"c1","body":{"type":"generate_ok","in_reply_to":1,"msg_id":1,"id":"n2-1"}}"#;
         let request: Message<String, GenerateBody<u32>, u32> =
@@ -0,0 +1,373 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    clock::{unix_millis, Clock, HybridLogicalClock},
+    workload::Rng,
+    Address, MessageId,
+};
+
+/// Strategy producing ids that are unique across the cluster
+///
+pub trait IdGenerator<A: Address, I: MessageId> {
+    /// Produces an id on `node_id`, `message_id` is the id of the `generate_ok` carrying it
+    ///
+    /// Fails if the generator can't guarantee uniqueness, e.g. because its state can't be persisted.
+    fn next_id(&mut self, node_id: &A, message_id: &I) -> io::Result<String>;
+}
+
+/// Numeric part of node ids such as `n12`, other ids are hashed
+fn node_number<A: Address>(node_id: &A) -> u64 {
+    let id = node_id.to_string();
+    let digits = id.trim_start_matches(|c: char| !c.is_ascii_digit());
+    digits.parse().unwrap_or_else(|_| {
+        id.bytes().fold(0xcbf2_9ce4_8422_2325, |hash: u64, byte| {
+            (hash ^ byte as u64).wrapping_mul(0x100_0000_01b3)
+        })
+    })
+}
+
+/// Millisecond timestamp paired with a counter that never goes backwards
+///
+/// The counter restarts whenever the clock moves forward, once it reaches `max` the timestamp is
+/// advanced past the clock instead of waiting for it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Stamp {
+    last: Option<(u64, u64)>,
+}
+
+impl Stamp {
+    fn next(&mut self, now: u64, max: u64) -> (u64, u64) {
+        let next = match self.last {
+            Some((millis, counter)) if now <= millis && counter < max => (millis, counter + 1),
+            Some((millis, _)) if now <= millis => (millis + 1, 0),
+            _ => (now, 0),
+        };
+        self.last = Some(next);
+        next
+    }
+}
+
+/// `"{node_id}-{message_id}"`, unique as long as message ids are never reused
+///
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Sequential;
+
+impl<A: Address, I: MessageId> IdGenerator<A, I> for Sequential {
+    fn next_id(&mut self, node_id: &A, message_id: &I) -> io::Result<String> {
+        Ok(format!(
+            "{}-{}",
+            node_id.to_string(),
+            message_id.to_string()
+        ))
+    }
+}
+
+/// Twitter style 64 bit ids: 41 bits of milliseconds since `epoch_ms`, 10 bits of node number and
+/// a 12 bit sequence, rendered in decimal
+///
+/// Ids are only handed out on nodes numbered below 1024, other node ids would collide.
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Snowflake {
+    epoch_ms: u64,
+    stamp: Stamp,
+}
+
+impl Snowflake {
+    /// 2020-01-01T00:00:00Z
+    pub const DEFAULT_EPOCH_MS: u64 = 1_577_836_800_000;
+
+    pub fn new(epoch_ms: u64) -> Self {
+        Self {
+            epoch_ms,
+            stamp: Stamp::default(),
+        }
+    }
+}
+
+impl Default for Snowflake {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_EPOCH_MS)
+    }
+}
+
+impl<A: Address, I: MessageId> IdGenerator<A, I> for Snowflake {
+    fn next_id(&mut self, node_id: &A, _message_id: &I) -> io::Result<String> {
+        let node = node_number(node_id);
+        if node > 0x3ff {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "node {} doesn't fit in the 10 bits of a snowflake id",
+                    node_id.to_string()
+                ),
+            ));
+        }
+        let now = unix_millis().saturating_sub(self.epoch_ms);
+        let (millis, sequence) = self.stamp.next(now, 0xfff);
+        let id = (millis & 0x1ff_ffff_ffff) << 22 | node << 12 | sequence;
+        Ok(id.to_string())
+    }
+}
+
+fn format_uuid(bits: u128) -> String {
+    let hex = format!("{bits:032x}");
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Random RFC 9562 version 4 UUIDs
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UuidV4 {
+    rng: Rng,
+}
+
+impl Default for UuidV4 {
+    fn default() -> Self {
+        Self {
+            rng: Rng::from_entropy(),
+        }
+    }
+}
+
+impl<A: Address, I: MessageId> IdGenerator<A, I> for UuidV4 {
+    fn next_id(&mut self, _node_id: &A, _message_id: &I) -> io::Result<String> {
+        let random = (self.rng.next_u64() as u128) << 64 | self.rng.next_u64() as u128;
+        let bits = random & !(0xf << 76 | 0x3 << 62) | 0x4 << 76 | 0x2 << 62;
+        Ok(format_uuid(bits))
+    }
+}
+
+/// Time ordered RFC 9562 version 7 UUIDs, the 12 bit `rand_a` field is used as a counter so ids
+/// from the same generator sort in creation order
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UuidV7 {
+    rng: Rng,
+    stamp: Stamp,
+}
+
+impl Default for UuidV7 {
+    fn default() -> Self {
+        Self {
+            rng: Rng::from_entropy(),
+            stamp: Stamp::default(),
+        }
+    }
+}
+
+impl<A: Address, I: MessageId> IdGenerator<A, I> for UuidV7 {
+    fn next_id(&mut self, _node_id: &A, _message_id: &I) -> io::Result<String> {
+        let (millis, counter) = self.stamp.next(unix_millis(), 0xfff);
+        let rand_b = self.rng.next_u64() & 0x3fff_ffff_ffff_ffff;
+        let bits = ((millis & 0xffff_ffff_ffff) as u128) << 80
+            | 0x7 << 76
+            | (counter as u128) << 64
+            | 0x2 << 62
+            | rand_b as u128;
+        Ok(format_uuid(bits))
+    }
+}
+
+/// Monotonic ULIDs: 48 bits of milliseconds and 80 random bits in Crockford base32, the random
+/// part is incremented for ids created within the same millisecond
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ulid {
+    rng: Rng,
+    last: Option<(u64, u128)>,
+}
+
+impl Default for Ulid {
+    fn default() -> Self {
+        Self {
+            rng: Rng::from_entropy(),
+            last: None,
+        }
+    }
+}
+
+impl<A: Address, I: MessageId> IdGenerator<A, I> for Ulid {
+    fn next_id(&mut self, _node_id: &A, _message_id: &I) -> io::Result<String> {
+        const RANDOM_MASK: u128 = (1 << 80) - 1;
+        let now = unix_millis();
+        let next = match self.last {
+            Some((millis, random)) if now <= millis && random < RANDOM_MASK => (millis, random + 1),
+            Some((millis, _)) if now <= millis => (millis + 1, 0),
+            _ => {
+                let random = (self.rng.next_u64() as u128) << 64 | self.rng.next_u64() as u128;
+                (now, random & RANDOM_MASK)
+            }
+        };
+        self.last = Some(next);
+
+        const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+        let bits = ((next.0 & 0xffff_ffff_ffff) as u128) << 80 | next.1;
+        Ok((0..26)
+            .map(|i| ALPHABET[(bits >> (125 - 5 * i) & 0x1f) as usize] as char)
+            .collect())
+    }
+}
+
+/// Hybrid logical clock ids `"{wall:012x}-{logical:08x}-{node_id}"`
+///
+/// They stay close to wall clock time, never go backwards on a node even if its clock does, and
+/// sort lexicographically in timestamp order across nodes.
+///
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HybridLogical {
+    clock: HybridLogicalClock,
+}
+
+impl<A: Address, I: MessageId> IdGenerator<A, I> for HybridLogical {
+    fn next_id(&mut self, node_id: &A, _message_id: &I) -> io::Result<String> {
+        let stamp = self.clock.tick();
+        Ok(format!(
+            "{:012x}-{:08x}-{}",
+            stamp.wall,
+            stamp.logical,
+            node_id.to_string()
+        ))
+    }
+}
+
+/// Ids `"{node_id}-{epoch}-{counter}"` that stay unique across restarts of a node
+///
+/// Before the first id is handed out the epoch stored in `{dir}/{node_id}.epoch` is incremented
+/// and synced to disk, so a restarted node never reuses the epoch of a previous run even if the
+/// counter starts from zero again.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Durable {
+    dir: PathBuf,
+    epoch: Option<u64>,
+    counter: u64,
+}
+
+impl Durable {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            epoch: None,
+            counter: 0,
+        }
+    }
+
+    /// Epoch of the current run, once it has been persisted
+    pub fn epoch(&self) -> Option<u64> {
+        self.epoch
+    }
+}
+
+impl<A: Address, I: MessageId> IdGenerator<A, I> for Durable {
+    fn next_id(&mut self, node_id: &A, _message_id: &I) -> io::Result<String> {
+        let epoch = match self.epoch {
+            Some(epoch) => epoch,
+            None => {
+                let path = self.dir.join(format!("{}.epoch", node_id.to_string()));
+                *self.epoch.insert(bump_epoch(&path)?)
+            }
+        };
+        self.counter += 1;
+        Ok(format!(
+            "{}-{}-{}",
+            node_id.to_string(),
+            epoch,
+            self.counter
+        ))
+    }
+}
+
+/// Atomically replaces the epoch in `path` with its successor
+fn bump_epoch(path: &Path) -> io::Result<u64> {
+    let previous = match fs::read_to_string(path) {
+        Ok(stored) => stored
+            .trim()
+            .parse::<u64>()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+        Err(err) => return Err(err),
+    };
+    let epoch = previous + 1;
+    let staged = path.with_extension("epoch.tmp");
+    let mut file = File::create(&staged)?;
+    write!(file, "{epoch}")?;
+    file.sync_all()?;
+    fs::rename(&staged, path)?;
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(epoch)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use super::{Durable, HybridLogical, IdGenerator, Snowflake, Ulid, UuidV4, UuidV7};
+
+    fn generate<G: IdGenerator<String, u32>>(generator: &mut G) -> Vec<String> {
+        let node = "n3".to_owned();
+        (0..5000)
+            .map(|i| generator.next_id(&node, &i).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_ids_are_unique_and_ordered() {
+        let snowflakes: Vec<u64> = generate(&mut Snowflake::default())
+            .iter()
+            .map(|id| id.parse().unwrap())
+            .collect();
+        assert!(snowflakes.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(snowflakes[0] >> 12 & 0x3ff, 3);
+        for node in ["n1024", "node"] {
+            let err = IdGenerator::<String, u32>::next_id(
+                &mut Snowflake::default(),
+                &node.to_owned(),
+                &1,
+            );
+            assert_eq!(err.unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+        }
+
+        for ids in [
+            generate(&mut UuidV7::default()),
+            generate(&mut Ulid::default()),
+            generate(&mut HybridLogical::default()),
+        ] {
+            assert!(ids.windows(2).all(|w| w[0] < w[1]), "{:?}", &ids[..2]);
+        }
+
+        let uuids = generate(&mut UuidV4::default());
+        assert_eq!(uuids.iter().collect::<HashSet<_>>().len(), uuids.len());
+        assert!(uuids.iter().all(|id| id.len() == 36 && &id[14..15] == "4"));
+        assert_eq!(generate(&mut Ulid::default())[0].len(), 26);
+    }
+
+    #[test]
+    fn test_durable_ids_survive_restart() {
+        let dir = std::env::temp_dir().join(format!("maelstrom-ids-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let before = generate(&mut Durable::new(&dir));
+        let mut restarted = Durable::new(&dir);
+        let after = generate(&mut restarted);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(before[0], "n3-1-1");
+        assert_eq!(after[0], "n3-2-1");
+        assert_eq!(restarted.epoch(), Some(2));
+        let ids: HashSet<_> = before.iter().chain(&after).collect();
+        assert_eq!(ids.len(), before.len() + after.len());
+    }
+}
@@ -1,7 +1,7 @@
-use serde::{Deserialize, Serialize};
-use std::{fmt::Debug, hash::Hash};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::VecDeque, fmt::Debug, hash::Hash};
 
-use crate::{error::Code, Address, MessageIndex, NodeId};
+use crate::{error::Code, Address, ClusterRegistry, InitGateRegistry, Message, MessageId};
 
 /// Body for initialization messages
 ///
@@ -9,7 +9,7 @@ use crate::{error::Code, Address, MessageIndex, NodeId};
 #[serde(tag = "type")]
 pub enum InitBody<I, A>
 where
-    I: MessageIndex,
+    I: MessageId,
     A: Address,
 {
     /// Init message request message
@@ -25,23 +25,64 @@ where
     Response { in_reply_to: I },
 }
 
+/// Reply to `init` plus the messages started by [`InitHandler::on_init`]
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Initialized<A, I, B>
+where
+    A: Address,
+    I: MessageId + DeserializeOwned + Serialize,
+    B: DeserializeOwned + Serialize,
+{
+    pub response: InitBody<I, A>,
+    pub peers: Vec<Message<A, B, I>>,
+}
+
 /// This trait has to be implement for every Node alongside any workload specific functionality
 ///
-pub trait InitHandler<A, I>: NodeId<A, I>
+/// `B` is the body of the messages the node handles, [`InitHandler::on_init`] sends messages with
+/// it, e.g. to start gossip or an election. The [`InitGateRegistry`] gate is marked initialized
+/// once `init` is accepted and rejects any later `init`.
+///
+pub trait InitHandler<A, I, B = InitBody<I, A>>:
+    ClusterRegistry<A, I> + InitGateRegistry<A, B, I>
 where
     A: Address,
-    I: MessageIndex,
+    I: MessageId + DeserializeOwned + Serialize,
+    B: DeserializeOwned + Serialize,
 {
-    fn respond_init(&mut self, request: InitBody<I, A>) -> Result<InitBody<I, A>, crate::Error<I>> {
+    /// Called once the node id and membership are stored, the returned messages are sent along
+    /// with `init_ok`
+    fn on_init(&mut self) -> Vec<Message<A, B, I>> {
+        Vec::new()
+    }
+
+    fn respond_init(
+        &mut self,
+        request: InitBody<I, A>,
+    ) -> Result<Initialized<A, I, B>, crate::Error<I>> {
         match request {
             InitBody::Request {
                 node_id,
                 message_id,
-                ..
+                node_ids,
             } => {
+                if self.init_gate().is_initialized() {
+                    return Err(crate::Error::new(
+                        message_id,
+                        Code::MalformedRequest,
+                        "Node is already initialized".to_owned(),
+                    ));
+                }
                 self.set_node_id(node_id)?;
-                Ok(InitBody::Response {
-                    in_reply_to: message_id,
+                self.set_node_ids(node_ids);
+                self.init_gate().initialize();
+                let peers = self.on_init();
+                Ok(Initialized {
+                    response: InitBody::Response {
+                        in_reply_to: message_id,
+                    },
+                    peers,
                 })
             }
             InitBody::Response { in_reply_to } => Err(crate::Error::new(
@@ -53,18 +94,140 @@ where
     }
 }
 
+/// What happens to workload messages that arrive before `init`
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PreInit {
+    /// Reply with `TemporarilyUnavailable`
+    Reject,
+    /// Hold up to `capacity` messages until `init_ok`, reject the rest
+    Buffer { capacity: usize },
+}
+
+/// Decision taken by an [`InitGate`] for an incoming message
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Admission<A, B, I>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    /// The message can be handled right away
+    Handle(Message<A, B, I>),
+    /// The message is held until [`InitGate::initialize`]
+    Buffered,
+    /// The message can't be handled, the error reply has to be sent back to its source
+    Rejected(Message<A, B, I>),
+    /// The message can't be handled and carries no `msg_id` to reply to
+    Dropped,
+}
+
+/// Keeps workload handlers from running before `init` has been answered
+///
+/// Messages whose body has type `init` are always let through before initialization and rejected
+/// after it. [`crate::runtime::run`] admits every message through the gate of its node and
+/// handles the buffered ones right after the message that initialized it.
+///
+#[derive(Clone, Debug)]
+pub struct InitGate<A, B, I>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    policy: PreInit,
+    initialized: bool,
+    buffered: VecDeque<Message<A, B, I>>,
+}
+
+impl<A, B, I> InitGate<A, B, I>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    pub fn new(policy: PreInit) -> Self {
+        Self {
+            policy,
+            initialized: false,
+            buffered: VecDeque::new(),
+        }
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.initialized
+    }
+
+    pub fn admit(&mut self, message: Message<A, B, I>) -> Admission<A, B, I> {
+        let is_init = message.body_field::<String>("type").as_deref() == Some("init");
+        match (self.initialized, is_init, self.policy) {
+            (false, true, _) | (true, false, _) => Admission::Handle(message),
+            (true, true, _) => reject(
+                message,
+                Code::MalformedRequest,
+                "Node is already initialized",
+            ),
+            (false, false, PreInit::Buffer { capacity }) if self.buffered.len() < capacity => {
+                self.buffered.push_back(message);
+                Admission::Buffered
+            }
+            (false, false, _) => reject(
+                message,
+                Code::TemporarilyUnavailable,
+                "Node is waiting for init",
+            ),
+        }
+    }
+
+    /// Marks `init` as answered, the buffered messages can be taken with [`InitGate::release`]
+    pub fn initialize(&mut self) {
+        self.initialized = true;
+    }
+
+    /// Takes the messages buffered before `init` in arrival order, none until initialized
+    pub fn release(&mut self) -> Vec<Message<A, B, I>> {
+        if !self.initialized {
+            return Vec::new();
+        }
+        self.buffered.drain(..).collect()
+    }
+}
+
+fn reject<A, B, I>(message: Message<A, B, I>, code: Code, text: &str) -> Admission<A, B, I>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    match crate::reply::ReplyHandle::new(&message) {
+        Some(reply) => Admission::Rejected(reply.fail(code, text.to_owned())),
+        None => Admission::Dropped,
+    }
+}
+
 #[cfg(test)]
 mod test {
 
-    use crate::{init::InitBody, Message, NodeId, ResponseBuilder};
+    use std::collections::HashSet;
 
-    use super::InitHandler;
+    use serde::{Deserialize, Serialize};
+
+    use crate::{
+        echo::EchoBody, error::Code, init::InitBody, ClusterRegistry, InitGateRegistry, Message,
+        NodeIdRegistry, ResponseBuilder,
+    };
+
+    use super::{Admission, InitGate, InitHandler, PreInit};
 
     pub struct TestNode {
         n: String,
+        nodes: Vec<String>,
+        gate: InitGate<String, EchoBody<u32>, u32>,
+        started: bool,
     }
 
-    impl NodeId<String, u32> for TestNode {
+    impl NodeIdRegistry<String, u32> for TestNode {
         fn node_id(&self) -> &String {
             &self.n
         }
@@ -75,7 +238,38 @@ mod test {
         }
     }
 
-    impl InitHandler<String, u32> for TestNode {}
+    impl ClusterRegistry<String, u32> for TestNode {
+        fn set_node_ids(&mut self, node_ids: Vec<String>) {
+            self.nodes = node_ids;
+        }
+
+        fn node_ids(&self) -> &[String] {
+            &self.nodes
+        }
+    }
+
+    impl InitGateRegistry<String, EchoBody<u32>, u32> for TestNode {
+        fn init_gate(&mut self) -> &mut InitGate<String, EchoBody<u32>, u32> {
+            &mut self.gate
+        }
+    }
+
+    impl InitHandler<String, u32, EchoBody<u32>> for TestNode {
+        fn on_init(&mut self) -> Vec<Message<String, EchoBody<u32>, u32>> {
+            self.started = true;
+            self.peers()
+                .into_iter()
+                .map(|peer| Message {
+                    source: self.n.clone(),
+                    destination: peer,
+                    body: Ok(EchoBody::Request {
+                        message_id: 1,
+                        echo: "started".to_owned(),
+                    }),
+                })
+                .collect()
+        }
+    }
     impl ResponseBuilder<String, u32, InitBody<u32, String>> for TestNode {}
 
     #[test]
@@ -92,16 +286,102 @@ mod test {
         }"#;
         let mut node = TestNode {
             n: "hello".to_owned(),
+            nodes: Vec::new(),
+            gate: InitGate::new(PreInit::Reject),
+            started: false,
         };
         let expected = r#"{"src":"123","dest":"321","body":{"type":"init_ok","in_reply_to":1}}"#;
         let request: Message<String, InitBody<u32, String>, u32> =
             serde_json::from_str(request).unwrap();
-        let response_body = request
+        let initialized = request
             .body
             .clone()
-            .and_then(|body| node.respond_init(body));
-        let response = TestNode::build_response(&request, response_body);
+            .and_then(|body| node.respond_init(body))
+            .unwrap();
+        let response = TestNode::build_response(&request, Ok(initialized.response));
         let res = serde_json::to_string(&response).unwrap();
         assert_eq!(expected, res);
+        assert!(node.started);
+        let started: Vec<&str> = initialized
+            .peers
+            .iter()
+            .map(|message| message.destination.as_str())
+            .collect();
+        assert_eq!(started, ["n1", "n2"]);
+        assert_eq!(node.peers(), ["n1", "n2"]);
+        assert_eq!(node.quorum(), 2);
+        let owner = node.owner(&"k1").unwrap();
+        assert!(node.node_ids().contains(owner));
+        let owners: HashSet<&String> = (0..64).filter_map(|key| node.owner(&key)).collect();
+        assert!(owners.len() > 1);
+    }
+
+    #[test]
+    fn test_repeat_init_without_nodes() {
+        let mut node = TestNode {
+            n: String::new(),
+            nodes: Vec::new(),
+            gate: InitGate::new(PreInit::Reject),
+            started: false,
+        };
+        let init = |node_id: &str| InitBody::Request {
+            message_id: 1,
+            node_id: node_id.to_owned(),
+            node_ids: Vec::new(),
+        };
+        node.respond_init(init("n1")).unwrap();
+        let err = node.respond_init(init("n2")).unwrap_err();
+        assert_eq!(err.code(), Code::MalformedRequest);
+        assert_eq!(node.node_id(), "n1");
+        assert!(node.gate.is_initialized());
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(untagged)]
+    pub enum Body {
+        Init(InitBody<u32, String>),
+        Echo(EchoBody<u32>),
+    }
+
+    #[test]
+    fn test_init_gate() {
+        let echo = |message_id| -> Message<String, Body, u32> {
+            Message {
+                source: "c1".to_owned(),
+                destination: "n1".to_owned(),
+                body: Ok(Body::Echo(EchoBody::Request {
+                    message_id,
+                    echo: "hi".to_owned(),
+                })),
+            }
+        };
+        let init = Message {
+            source: "c0".to_owned(),
+            destination: "n1".to_owned(),
+            body: Ok(Body::Init(InitBody::Request {
+                message_id: 1,
+                node_id: "n1".to_owned(),
+                node_ids: vec!["n1".to_owned()],
+            })),
+        };
+        let mut gate = InitGate::new(PreInit::Buffer { capacity: 1 });
+        assert_eq!(gate.admit(echo(2)), Admission::Buffered);
+        let Admission::Rejected(rejected) = gate.admit(echo(3)) else {
+            panic!("full buffer has to reject");
+        };
+        assert_eq!(
+            rejected.body.unwrap_err().code(),
+            Code::TemporarilyUnavailable
+        );
+
+        assert_eq!(gate.admit(init.clone()), Admission::Handle(init.clone()));
+        assert!(gate.release().is_empty());
+        gate.initialize();
+        assert_eq!(gate.release(), vec![echo(2)]);
+        assert_eq!(gate.admit(echo(4)), Admission::Handle(echo(4)));
+        let Admission::Rejected(rejected) = gate.admit(init) else {
+            panic!("second init has to be rejected");
+        };
+        assert_eq!(rejected.destination, "c0");
     }
 }
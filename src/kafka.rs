@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::MessageId;
+
+/// Body for the `kafka` workload, logs are identified by string keys and entries by offset
+///
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "type")]
+pub enum KafkaBody<I, T>
+where
+    I: MessageId,
+{
+    #[serde(rename = "send")]
+    SendRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        key: String,
+        msg: T,
+    },
+    #[serde(rename = "send_ok")]
+    SendResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        offset: u64,
+    },
+    #[serde(rename = "poll")]
+    PollRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        offsets: HashMap<String, u64>,
+    },
+    #[serde(rename = "poll_ok")]
+    PollResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        msgs: HashMap<String, Vec<(u64, T)>>,
+    },
+    #[serde(rename = "commit_offsets")]
+    CommitOffsetsRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        offsets: HashMap<String, u64>,
+    },
+    #[serde(rename = "commit_offsets_ok")]
+    CommitOffsetsResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+    },
+    #[serde(rename = "list_committed_offsets")]
+    ListCommittedOffsetsRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        keys: Vec<String>,
+    },
+    #[serde(rename = "list_committed_offsets_ok")]
+    ListCommittedOffsetsResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        offsets: HashMap<String, u64>,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::Message;
+
+    use super::KafkaBody;
+
+    #[test]
+    fn test_serialize_poll_ok() {
+        let response: Message<String, KafkaBody<u32, u64>, u32> = Message {
+            source: "n1".to_owned(),
+            destination: "c1".to_owned(),
+            body: Ok(KafkaBody::PollResponse {
+                in_reply_to: 2,
+                message_id: 5,
+                msgs: HashMap::from([("k1".to_owned(), vec![(1000, 9), (1001, 5)])]),
+            }),
+        };
+        let expected = r#"{"src":"n1","dest":"c1","body":{"type":"poll_ok","in_reply_to":2,"msg_id":5,"msgs":{"k1":[[1000,9],[1001,5]]}}}"#;
+        assert_eq!(serde_json::to_string(&response).unwrap(), expected);
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::{fmt::Debug, hash::Hash};
+
+use crate::MessageId;
+
+/// Body for the `lin-kv`, `seq-kv` and `lww-kv` services
+///
+/// Failed operations are answered with `KeyDoesNotExist` or `PreconditionFailed` errors. Services
+/// don't necessarily stamp their replies with a `msg_id`.
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[serde(tag = "type")]
+pub enum KvBody<I, K, V>
+where
+    I: MessageId,
+{
+    #[serde(rename = "read")]
+    ReadRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        key: K,
+    },
+    #[serde(rename = "read_ok")]
+    ReadResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id", skip_serializing_if = "Option::is_none")]
+        message_id: Option<I>,
+        value: V,
+    },
+    #[serde(rename = "write")]
+    WriteRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        key: K,
+        value: V,
+    },
+    #[serde(rename = "write_ok")]
+    WriteResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id", skip_serializing_if = "Option::is_none")]
+        message_id: Option<I>,
+    },
+    #[serde(rename = "cas")]
+    CasRequest {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        key: K,
+        from: V,
+        to: V,
+        #[serde(default)]
+        create_if_not_exists: bool,
+    },
+    #[serde(rename = "cas_ok")]
+    CasResponse {
+        in_reply_to: I,
+        #[serde(rename = "msg_id", skip_serializing_if = "Option::is_none")]
+        message_id: Option<I>,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{error::Code, Message};
+
+    use super::KvBody;
+
+    #[test]
+    fn test_parse_kv_replies() {
+        let cas_ok = r#"{"src":"lin-kv","dest":"n1","body":{"type":"cas_ok","in_reply_to":4}}"#;
+        let cas_ok: Message<String, KvBody<u32, String, u64>, u32> =
+            serde_json::from_str(cas_ok).unwrap();
+        assert_eq!(
+            cas_ok.body,
+            Ok(KvBody::CasResponse {
+                in_reply_to: 4,
+                message_id: None
+            })
+        );
+
+        let failed = r#"{"src":"lin-kv","dest":"n1","body":{"type":"error","in_reply_to":5,"code":22,"text":"expected 0, but had 3"}}"#;
+        let failed: Message<String, KvBody<u32, String, u64>, u32> =
+            serde_json::from_str(failed).unwrap();
+        assert_eq!(failed.body.unwrap_err().code(), Code::PreconditionFailed);
+    }
+}
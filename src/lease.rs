@@ -0,0 +1,323 @@
+use std::collections::VecDeque;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    error::Code, generate::GenerateBody, kv::KvBody, reply::ReplyHandle, Address, Message,
+    MessageId, MessageIdRegistry,
+};
+
+pub type GenerateMessage<A, I> = Message<A, GenerateBody<I, u64>, I>;
+pub type KvMessage<A, I> = Message<A, KvBody<I, String, u64>, I>;
+
+/// Messages to send after a [`BlockLease`] step
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Leased<A, I>
+where
+    A: Address,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    /// `generate_ok` replies to clients
+    pub replies: Vec<GenerateMessage<A, I>>,
+    /// Request to the key-value service
+    pub kv_request: Option<KvMessage<A, I>>,
+}
+
+impl<A, I> Default for Leased<A, I>
+where
+    A: Address,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    fn default() -> Self {
+        Self {
+            replies: Vec::new(),
+            kv_request: None,
+        }
+    }
+}
+
+/// Serves small, roughly sequential integer ids from blocks leased through a `lin-kv` counter
+///
+/// The counter under `key` holds the first id not leased yet. A block is taken by moving it
+/// forward with `cas`, on a conflict the current value is read and the `cas` retried. Requests
+/// arriving while no ids are left are answered once the next block is leased.
+///
+#[derive(Clone, Debug)]
+pub struct BlockLease<A: Address, I: MessageId> {
+    service: A,
+    key: String,
+    block_size: u64,
+    next: u64,
+    end: u64,
+    guess: u64,
+    node: Option<A>,
+    in_flight: Option<I>,
+    waiting: VecDeque<ReplyHandle<A, I>>,
+}
+
+impl<A, I> BlockLease<A, I>
+where
+    A: Address,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    pub fn new(service: A, key: impl Into<String>, block_size: u64) -> Self {
+        Self {
+            service,
+            key: key.into(),
+            block_size: block_size.max(1),
+            next: 0,
+            end: 0,
+            guess: 0,
+            node: None,
+            in_flight: None,
+            waiting: VecDeque::new(),
+        }
+    }
+
+    /// Ids left in the current block
+    pub fn remaining(&self) -> u64 {
+        self.end - self.next
+    }
+
+    pub fn respond_generate<R>(
+        &mut self,
+        request: &GenerateMessage<A, I>,
+        ids: &mut R,
+    ) -> Result<Leased<A, I>, crate::Error<I>>
+    where
+        R: MessageIdRegistry<I>,
+    {
+        let Some(reply) = ReplyHandle::new(request) else {
+            return Ok(Leased::default());
+        };
+        if !matches!(request.body, Ok(GenerateBody::Request { .. })) {
+            return Err(crate::Error::new(
+                reply.in_reply_to().clone(),
+                Code::MalformedRequest,
+                "Request is a response".to_owned(),
+            ));
+        }
+        self.node = Some(request.destination.clone());
+        self.waiting.push_back(reply);
+        Ok(self.serve(ids))
+    }
+
+    /// Advances the lease with a reply from the key-value service
+    pub fn handle_kv<R>(&mut self, reply: &KvMessage<A, I>, ids: &mut R) -> Leased<A, I>
+    where
+        R: MessageIdRegistry<I>,
+    {
+        let in_reply_to = match &reply.body {
+            Ok(
+                KvBody::ReadResponse { in_reply_to, .. } | KvBody::CasResponse { in_reply_to, .. },
+            ) => in_reply_to,
+            Err(err) => err.in_reply_to(),
+            Ok(_) => return Leased::default(),
+        };
+        if self.in_flight.as_ref() != Some(in_reply_to) {
+            return Leased::default();
+        }
+        self.in_flight = None;
+        match &reply.body {
+            Ok(KvBody::CasResponse { .. }) => {
+                self.next = self.guess;
+                self.end = self.guess + self.block_size;
+                self.guess = self.end;
+                self.serve(ids)
+            }
+            Ok(KvBody::ReadResponse { value, .. }) => {
+                self.guess = *value;
+                self.request(ids, true)
+            }
+            Err(err) if err.code() == Code::KeyDoesNotExist => {
+                self.guess = 0;
+                self.request(ids, true)
+            }
+            _ => self.request(ids, false),
+        }
+    }
+
+    /// Re-issues the outstanding service request, for when it may have been lost
+    pub fn retry<R>(&mut self, ids: &mut R) -> Leased<A, I>
+    where
+        R: MessageIdRegistry<I>,
+    {
+        match self.in_flight {
+            Some(_) => self.request(ids, false),
+            None => Leased::default(),
+        }
+    }
+
+    fn serve<R>(&mut self, ids: &mut R) -> Leased<A, I>
+    where
+        R: MessageIdRegistry<I>,
+    {
+        let mut replies = Vec::new();
+        while self.next < self.end {
+            let Some(reply) = self.waiting.pop_front() else {
+                break;
+            };
+            let body = GenerateBody::Response {
+                in_reply_to: reply.in_reply_to().clone(),
+                message_id: ids.gen_msg_id(),
+                id: self.next,
+            };
+            self.next += 1;
+            replies.push(reply.complete(Ok(body)));
+        }
+        let mut leased = match (self.waiting.is_empty(), &self.in_flight) {
+            (false, None) => self.request(ids, true),
+            _ => Leased::default(),
+        };
+        leased.replies = replies;
+        leased
+    }
+
+    /// Sends `cas` for the block starting at the guessed counter, or `read` to refresh the guess
+    fn request<R>(&mut self, ids: &mut R, cas: bool) -> Leased<A, I>
+    where
+        R: MessageIdRegistry<I>,
+    {
+        let Some(node) = self.node.clone() else {
+            return Leased::default();
+        };
+        let message_id = ids.gen_msg_id();
+        self.in_flight = Some(message_id.clone());
+        let key = self.key.clone();
+        let body = if cas {
+            KvBody::CasRequest {
+                message_id,
+                key,
+                from: self.guess,
+                to: self.guess + self.block_size,
+                create_if_not_exists: self.guess == 0,
+            }
+        } else {
+            KvBody::ReadRequest { message_id, key }
+        };
+        Leased {
+            replies: Vec::new(),
+            kv_request: Some(Message {
+                source: node,
+                destination: self.service.clone(),
+                body: Ok(body),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{error::Code, generate::GenerateBody, kv::KvBody, Message, MessageIdRegistry};
+
+    use super::{BlockLease, GenerateMessage, KvMessage};
+
+    #[derive(Default)]
+    pub struct TestNode {
+        n: u32,
+    }
+
+    impl MessageIdRegistry<u32> for TestNode {
+        fn gen_msg_id(&mut self) -> u32 {
+            self.n += 1;
+            self.n
+        }
+    }
+
+    fn generate(message_id: u32) -> GenerateMessage<String, u32> {
+        Message {
+            source: "c1".to_owned(),
+            destination: "n1".to_owned(),
+            body: Ok(GenerateBody::Request { message_id }),
+        }
+    }
+
+    fn from_kv(
+        body: Result<KvBody<u32, String, u64>, crate::Error<u32>>,
+    ) -> KvMessage<String, u32> {
+        Message {
+            source: "lin-kv".to_owned(),
+            destination: "n1".to_owned(),
+            body,
+        }
+    }
+
+    fn leased_ids(replies: &[GenerateMessage<String, u32>]) -> Vec<u64> {
+        replies
+            .iter()
+            .map(|reply| match reply.body {
+                Ok(GenerateBody::Response { id, .. }) => id,
+                _ => panic!("expected generate_ok"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_block_lease() {
+        let mut node = TestNode::default();
+        let mut lease = BlockLease::new("lin-kv".to_owned(), "ids", 2);
+
+        let leased = lease.respond_generate(&generate(1), &mut node).unwrap();
+        assert!(leased.replies.is_empty());
+        let cas = serde_json::to_string(&leased.kv_request.unwrap()).unwrap();
+        assert_eq!(
+            cas,
+            r#"{"src":"n1","dest":"lin-kv","body":{"type":"cas","msg_id":1,"key":"ids","from":0,"to":2,"create_if_not_exists":true}}"#
+        );
+
+        let cas_ok = from_kv(Ok(KvBody::CasResponse {
+            in_reply_to: 1,
+            message_id: None,
+        }));
+        let leased = lease.handle_kv(&cas_ok, &mut node);
+        assert_eq!(leased_ids(&leased.replies), [0]);
+        let leased = lease.respond_generate(&generate(2), &mut node).unwrap();
+        assert_eq!(leased_ids(&leased.replies), [1]);
+
+        let leased = lease.respond_generate(&generate(3), &mut node).unwrap();
+        let Some(Message {
+            body: Ok(KvBody::CasRequest {
+                message_id, from, ..
+            }),
+            ..
+        }) = leased.kv_request
+        else {
+            panic!("expected cas");
+        };
+        assert_eq!(from, 2);
+
+        let conflict = crate::Error::new(message_id, Code::PreconditionFailed, String::new());
+        let leased = lease.handle_kv(&from_kv(Err(conflict)), &mut node);
+        let Some(Message {
+            body: Ok(KvBody::ReadRequest { message_id, .. }),
+            ..
+        }) = leased.kv_request
+        else {
+            panic!("expected read");
+        };
+
+        let read_ok = from_kv(Ok(KvBody::ReadResponse {
+            in_reply_to: message_id,
+            message_id: None,
+            value: 10,
+        }));
+        let Some(Message {
+            body: Ok(KvBody::CasRequest {
+                message_id, from, ..
+            }),
+            ..
+        }) = lease.handle_kv(&read_ok, &mut node).kv_request
+        else {
+            panic!("expected cas");
+        };
+        assert_eq!(from, 10);
+        let cas_ok = from_kv(Ok(KvBody::CasResponse {
+            in_reply_to: message_id,
+            message_id: None,
+        }));
+        let leased = lease.handle_kv(&cas_ok, &mut node);
+        assert_eq!(leased_ids(&leased.replies), [10]);
+        assert_eq!(lease.remaining(), 1);
+    }
+}
@@ -1,17 +1,42 @@
+#[cfg(feature = "async")]
+pub mod async_runtime;
 mod error;
-pub use error::Error;
+pub use error::{Code, CustomCode, Error};
 pub mod broadcast;
+pub mod clock;
+pub mod counter;
 pub mod echo;
+pub mod forward;
+pub mod g_set;
 pub mod generate;
+pub mod id;
 pub mod init;
+pub mod kafka;
+pub mod kv;
+pub mod lease;
+pub mod list_append;
+pub mod pn_counter;
+pub mod reply;
+pub mod runtime;
+pub mod stats;
+pub mod store;
 pub mod topology;
+pub mod workload;
 
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use std::{fmt::Debug, hash::Hash};
+use std::{
+    fmt::Debug,
+    hash::{DefaultHasher, Hash, Hasher},
+};
 
 /// Unique identifier for a node
 ///
-pub trait Address: ToString + Clone + Debug + Eq + PartialEq + Hash {}
+pub trait Address: ToString + Clone + Debug + Eq + PartialEq + Hash {
+    /// Maelstrom names clients `c1`, `c2`, ... while nodes and services use other prefixes
+    fn is_client(&self) -> bool {
+        self.to_string().starts_with('c')
+    }
+}
 
 impl Address for String {}
 impl Address for &str {}
@@ -28,17 +53,122 @@ pub trait NodeIdRegistry<A: Address, I: MessageId> {
     fn node_id(&self) -> &A;
 }
 
+/// Stores the cluster membership announced by `init`
+///
+pub trait ClusterRegistry<A: Address, I: MessageId>: NodeIdRegistry<A, I> {
+    fn set_node_ids(&mut self, node_ids: Vec<A>);
+    /// Every node in the cluster, including this one, in the order given by `init`
+    fn node_ids(&self) -> &[A];
+
+    /// Every node in the cluster except this one
+    fn peers(&self) -> Vec<A> {
+        self.node_ids()
+            .iter()
+            .filter(|id| *id != self.node_id())
+            .cloned()
+            .collect()
+    }
+
+    /// Smallest number of nodes forming a majority
+    fn quorum(&self) -> usize {
+        self.node_ids().len() / 2 + 1
+    }
+
+    /// Node responsible for `key`, the same on every node that received the same `init`
+    fn owner<K: Hash>(&self, key: &K) -> Option<&A> {
+        let nodes = self.node_ids();
+        if nodes.is_empty() {
+            return None;
+        }
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        nodes.get((hasher.finish() % nodes.len() as u64) as usize)
+    }
+}
+
 pub trait MessageIdRegistry<I: MessageId> {
     fn gen_msg_id(&mut self) -> I;
 }
 
+/// Holds the id strategy used to answer `generate` requests
+///
+pub trait IdGeneratorRegistry<A: Address, I: MessageId> {
+    type Generator: id::IdGenerator<A, I>;
+    fn id_generator(&mut self) -> &mut Self::Generator;
+}
+
+/// Holds the [`init::InitGate`] recording whether `init` has been answered
+///
+pub trait InitGateRegistry<A, B, I>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    fn init_gate(&mut self) -> &mut init::InitGate<A, B, I>;
+}
+
+/// Holds the causal delivery state used by [`broadcast::causal::CausalBroadcastHandler`]
+///
+pub trait CausalRegistry<A: Address, I: MessageId, T> {
+    fn causal_broadcast(&mut self) -> &mut broadcast::causal::CausalBroadcast<A, I, T>;
+}
+
+/// Holds the pushes waiting for acks in [`broadcast::durable::DurableBroadcastHandler`]
+///
+pub trait DurableRegistry<A: Address, I: MessageId, T> {
+    fn durable(&mut self) -> &mut broadcast::durable::Durable<A, I, T>;
+}
+
+/// Holds the sequencing state used by [`broadcast::total_order::TotalOrderBroadcastHandler`]
+///
+pub trait TotalOrderRegistry<A: Address, I: MessageId, T> {
+    fn total_order(&mut self) -> &mut broadcast::total_order::TotalOrder<A, I, T>;
+}
+
+/// Holds the values a node has seen
+///
 pub trait MessageRegistry<T> {
-    fn push_msg(&mut self, msg: T);
-    fn messages(&self) -> &[T];
+    type Store: store::MessageStore<T>;
+    fn messages(&self) -> &Self::Store;
+    fn messages_mut(&mut self) -> &mut Self::Store;
+
+    /// Stores `msg`, `true` if it wasn't stored before
+    fn push_msg(&mut self, msg: T) -> bool {
+        store::MessageStore::insert(self.messages_mut(), msg)
+    }
+}
+
+/// Holds the elements of the grow-only set served by [`g_set::GSetHandler`]
+///
+pub trait GSetRegistry<T> {
+    type Store: store::MessageStore<T>;
+    fn g_set(&self) -> &Self::Store;
+    fn g_set_mut(&mut self) -> &mut Self::Store;
+}
+
+/// Holds the storage backend running [`list_append::ListAppendHandler`] transactions
+///
+pub trait ListAppendRegistry<K, V> {
+    type Store: list_append::ListStore<K, V>;
+    fn lists(&mut self) -> &mut Self::Store;
+}
+
+/// Holds the tallies used by [`pn_counter::PnCounterHandler`]
+///
+pub trait PnCounterRegistry<A: Address> {
+    fn pn_counter(&mut self) -> &mut pn_counter::PnCounter<A>;
 }
 
 pub trait TopologyRegistry<A: Address> {
     fn set_topology(&mut self, topology: Vec<A>);
+    fn topology(&self) -> &[A];
+}
+
+/// Holds the broadcast tree used by [`broadcast::plumtree::PlumtreeHandler`]
+///
+pub trait PlumtreeRegistry<A: Address> {
+    fn plumtree(&mut self) -> &mut broadcast::plumtree::Plumtree<A>;
 }
 
 #[derive(Serialize, Deserialize)]
@@ -65,6 +195,56 @@ where
     pub body: Result<B, crate::Error<I>>,
 }
 
+impl<A, B, I> Message<A, B, I>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    /// Reads a field such as `msg_id` or `in_reply_to` from the wire representation of the body
+    pub(crate) fn body_field<T: DeserializeOwned>(&self, name: &str) -> Option<T> {
+        take_field(&mut self.body_value()?, name)
+    }
+
+    /// Wire representation of the body, for reading several fields at once with [`take_field`]
+    pub(crate) fn body_value(&self) -> Option<serde_json::Value> {
+        match &self.body {
+            Ok(body) => serde_json::to_value(body),
+            Err(err) => serde_json::to_value(err),
+        }
+        .ok()
+    }
+
+    /// Overwrites an existing field of the wire representation of the body, returns whether the
+    /// body accepted the new value
+    pub(crate) fn set_body_field<T: Serialize>(&mut self, name: &str, value: T) -> bool {
+        fn replace<S, T>(body: &S, name: &str, value: T) -> Option<S>
+        where
+            S: DeserializeOwned + Serialize,
+            T: Serialize,
+        {
+            let mut body = serde_json::to_value(body).ok()?;
+            *body.get_mut(name)? = serde_json::to_value(value).ok()?;
+            serde_json::from_value(body).ok()
+        }
+        let body = match &self.body {
+            Ok(body) => replace(body, name, value).map(Ok),
+            Err(err) => replace(err, name, value).map(Err),
+        };
+        body.map(|body| self.body = body).is_some()
+    }
+}
+
+/// Moves the field `name` out of a body read with [`Message::body_value`]
+pub(crate) fn take_field<T: DeserializeOwned>(
+    body: &mut serde_json::Value,
+    name: &str,
+) -> Option<T> {
+    body.get_mut(name)
+        .map(serde_json::Value::take)
+        .and_then(|field| serde_json::from_value(field).ok())
+}
+
 /// This trait determines the source address of outcoming packages
 ///
 pub trait ResponseBuilder<
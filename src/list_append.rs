@@ -0,0 +1,291 @@
+use serde::{
+    de::{self, DeserializeOwned, SeqAccess, Visitor},
+    ser::SerializeTuple,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{collections::HashMap, fmt, hash::Hash, marker::PhantomData};
+
+use crate::{error::Code, ListAppendRegistry, MessageId, MessageIdRegistry};
+
+/// Operation of a `txn-list-append` transaction
+///
+/// Serialized the way Maelstrom and Elle expect, as `["append", k, v]` and `["r", k, values]`.
+/// Reads are sent with `null` values and answered with the whole list, or `null` for keys that
+/// were never appended to.
+///
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum MicroOp<K, V> {
+    Append(K, V),
+    Read(K, Option<Vec<V>>),
+}
+
+impl<K: Serialize, V: Serialize> Serialize for MicroOp<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut tuple = serializer.serialize_tuple(3)?;
+        match self {
+            MicroOp::Append(key, value) => {
+                tuple.serialize_element("append")?;
+                tuple.serialize_element(key)?;
+                tuple.serialize_element(value)?;
+            }
+            MicroOp::Read(key, values) => {
+                tuple.serialize_element("r")?;
+                tuple.serialize_element(key)?;
+                tuple.serialize_element(values)?;
+            }
+        }
+        tuple.end()
+    }
+}
+
+impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for MicroOp<K, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OpVisitor<K, V>(PhantomData<(K, V)>);
+
+        impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for OpVisitor<K, V> {
+            type Value = MicroOp<K, V>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str(r#"["append", key, value] or ["r", key, values]"#)
+            }
+
+            fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Self::Value, S::Error> {
+                let function: String = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let key = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                match function.as_str() {
+                    "append" => {
+                        let value = seq
+                            .next_element()?
+                            .ok_or_else(|| de::Error::invalid_length(2, &self))?;
+                        Ok(MicroOp::Append(key, value))
+                    }
+                    "r" => Ok(MicroOp::Read(key, seq.next_element()?.flatten())),
+                    other => Err(de::Error::unknown_variant(other, &["append", "r"])),
+                }
+            }
+        }
+
+        deserializer.deserialize_seq(OpVisitor(PhantomData))
+    }
+}
+
+/// Body for the `txn-list-append` workload
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize, Hash)]
+#[serde(tag = "type")]
+#[serde(bound(
+    serialize = "I: Serialize, K: Serialize, V: Serialize",
+    deserialize = "I: DeserializeOwned, K: DeserializeOwned, V: DeserializeOwned"
+))]
+pub enum TxnBody<I, K, V>
+where
+    I: MessageId,
+{
+    #[serde(rename = "txn")]
+    Request {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        txn: Vec<MicroOp<K, V>>,
+    },
+    #[serde(rename = "txn_ok")]
+    Response {
+        in_reply_to: I,
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        txn: Vec<MicroOp<K, V>>,
+    },
+}
+
+/// Storage backend executing whole transactions
+///
+/// The backend decides the isolation level, a failed transaction is answered with the returned
+/// code, typically `TxnConflict` or `Abort`, and must leave no visible effects.
+///
+pub trait ListStore<K, V> {
+    /// Executes `txn` and returns it with every read filled in
+    fn execute(&mut self, txn: Vec<MicroOp<K, V>>) -> Result<Vec<MicroOp<K, V>>, (Code, String)>;
+}
+
+/// Lists held in memory by a single node, every transaction runs in isolation
+///
+#[derive(Clone, Debug)]
+pub struct MemoryLists<K, V> {
+    lists: HashMap<K, Vec<V>>,
+}
+
+impl<K, V> Default for MemoryLists<K, V> {
+    fn default() -> Self {
+        Self {
+            lists: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> MemoryLists<K, V> {
+    pub fn get(&self, key: &K) -> Option<&[V]> {
+        self.lists.get(key).map(Vec::as_slice)
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> ListStore<K, V> for MemoryLists<K, V> {
+    fn execute(
+        &mut self,
+        mut txn: Vec<MicroOp<K, V>>,
+    ) -> Result<Vec<MicroOp<K, V>>, (Code, String)> {
+        for op in &mut txn {
+            match op {
+                MicroOp::Append(key, value) => match self.lists.get_mut(key) {
+                    Some(list) => list.push(value.clone()),
+                    None => {
+                        self.lists.insert(key.clone(), vec![value.clone()]);
+                    }
+                },
+                MicroOp::Read(key, values) => *values = self.lists.get(key).cloned(),
+            }
+        }
+        Ok(txn)
+    }
+}
+
+/// Handles the `txn-list-append` workload by running transactions against the
+/// [`ListAppendRegistry`] store
+///
+pub trait ListAppendHandler<I, K, V>: MessageIdRegistry<I> + ListAppendRegistry<K, V>
+where
+    I: MessageId,
+{
+    fn respond_txn(
+        &mut self,
+        request: TxnBody<I, K, V>,
+    ) -> Result<TxnBody<I, K, V>, crate::Error<I>> {
+        match request {
+            TxnBody::Request { message_id, txn } => match self.lists().execute(txn) {
+                Ok(txn) => Ok(TxnBody::Response {
+                    in_reply_to: message_id,
+                    message_id: self.gen_msg_id(),
+                    txn,
+                }),
+                Err((code, text)) => Err(crate::Error::new(message_id, code, text)),
+            },
+            TxnBody::Response { message_id, .. } => Err(crate::Error::new(
+                message_id,
+                Code::MalformedRequest,
+                "Request is response".to_owned(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{error::Code, ListAppendRegistry, Message, MessageIdRegistry, ResponseBuilder};
+
+    use super::{ListAppendHandler, ListStore, MemoryLists, MicroOp, TxnBody};
+
+    #[derive(Default)]
+    pub struct TestNode {
+        n: u32,
+        lists: MemoryLists<u64, u64>,
+    }
+
+    impl MessageIdRegistry<u32> for TestNode {
+        fn gen_msg_id(&mut self) -> u32 {
+            self.n += 1;
+            self.n
+        }
+    }
+
+    impl ListAppendRegistry<u64, u64> for TestNode {
+        type Store = MemoryLists<u64, u64>;
+        fn lists(&mut self) -> &mut MemoryLists<u64, u64> {
+            &mut self.lists
+        }
+    }
+
+    impl ListAppendHandler<u32, u64, u64> for TestNode {}
+    impl ResponseBuilder<String, u32, TxnBody<u32, u64, u64>> for TestNode {}
+
+    #[test]
+    fn test_list_append_txn() {
+        let request = r#"{
+          "src": "c1",
+          "dest": "n1",
+          "body": {
+            "type": "txn",
+            "msg_id": 3,
+            "txn": [["r", 1, null], ["append", 1, 6], ["append", 2, 7], ["r", 1, null]]
+          }
+        } "#;
+        let request: Message<String, TxnBody<u32, u64, u64>, u32> =
+            serde_json::from_str(request).unwrap();
+        let mut test_node = TestNode::default();
+        test_node
+            .respond_txn(TxnBody::Request {
+                message_id: 1,
+                txn: vec![MicroOp::Append(1, 5)],
+            })
+            .unwrap();
+        let response = request
+            .body
+            .clone()
+            .and_then(|body| test_node.respond_txn(body));
+        let response = TestNode::build_response(&request, response);
+        let expected = r#"{"src":"n1","dest":"c1","body":{"type":"txn_ok","in_reply_to":3,"msg_id":2,"txn":[["r",1,[5]],["append",1,6],["append",2,7],["r",1,[5,6]]]}}"#;
+        assert_eq!(serde_json::to_string(&response).unwrap(), expected);
+        assert_eq!(test_node.lists.get(&2), Some([7].as_slice()));
+        assert_eq!(test_node.lists.get(&3), None);
+    }
+
+    /// Store aborting every transaction
+    pub struct Conflicting;
+
+    impl ListStore<u64, u64> for Conflicting {
+        fn execute(
+            &mut self,
+            _txn: Vec<MicroOp<u64, u64>>,
+        ) -> Result<Vec<MicroOp<u64, u64>>, (Code, String)> {
+            Err((Code::TxnConflict, "Conflicting write".to_owned()))
+        }
+    }
+
+    pub struct ConflictNode {
+        store: Conflicting,
+    }
+
+    impl MessageIdRegistry<u32> for ConflictNode {
+        fn gen_msg_id(&mut self) -> u32 {
+            1
+        }
+    }
+
+    impl ListAppendRegistry<u64, u64> for ConflictNode {
+        type Store = Conflicting;
+        fn lists(&mut self) -> &mut Conflicting {
+            &mut self.store
+        }
+    }
+
+    impl ListAppendHandler<u32, u64, u64> for ConflictNode {}
+    impl ResponseBuilder<String, u32, TxnBody<u32, u64, u64>> for ConflictNode {}
+
+    #[test]
+    fn test_list_append_conflict() {
+        let request: Message<String, TxnBody<u32, u64, u64>, u32> = Message {
+            source: "c1".to_owned(),
+            destination: "n1".to_owned(),
+            body: Ok(TxnBody::Request {
+                message_id: 5,
+                txn: vec![MicroOp::Append(1, 2)],
+            }),
+        };
+        let mut node = ConflictNode { store: Conflicting };
+        let response = request.body.clone().and_then(|body| node.respond_txn(body));
+        let response = ConflictNode::build_response(&request, response);
+        let expected = r#"{"src":"n1","dest":"c1","body":{"type":"error","in_reply_to":5,"code":30,"text":"Conflicting write"}}"#;
+        assert_eq!(serde_json::to_string(&response).unwrap(), expected);
+    }
+}
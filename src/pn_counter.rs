@@ -0,0 +1,239 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{collections::HashMap, fmt::Debug};
+
+use crate::{
+    counter::CounterBody, error::Code, Address, ClusterRegistry, Message, MessageId,
+    MessageIdRegistry, PnCounterRegistry,
+};
+
+/// Per node totals of the positive and negative deltas added at that node
+///
+/// Every entry only grows and is only written by its own node, so merging two counters takes the
+/// larger value of each entry and repeated or reordered merges converge to the same value.
+///
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(bound(serialize = "A: Serialize", deserialize = "A: DeserializeOwned"))]
+pub struct PnCounter<A: Address> {
+    increments: HashMap<A, u64>,
+    decrements: HashMap<A, u64>,
+}
+
+impl<A: Address> Default for PnCounter<A> {
+    fn default() -> Self {
+        Self {
+            increments: HashMap::new(),
+            decrements: HashMap::new(),
+        }
+    }
+}
+
+impl<A: Address> PnCounter<A> {
+    /// Adds `delta` to the tallies of `node`
+    pub fn add(&mut self, node: A, delta: i64) {
+        let tally = if delta < 0 {
+            &mut self.decrements
+        } else {
+            &mut self.increments
+        };
+        *tally.entry(node).or_default() += delta.unsigned_abs();
+    }
+
+    pub fn value(&self) -> i64 {
+        let increments: u64 = self.increments.values().sum();
+        let decrements: u64 = self.decrements.values().sum();
+        increments as i64 - decrements as i64
+    }
+
+    pub fn merge(&mut self, other: &Self) {
+        for (mine, theirs) in [
+            (&mut self.increments, &other.increments),
+            (&mut self.decrements, &other.decrements),
+        ] {
+            for (node, count) in theirs {
+                let entry = mine.entry(node.clone()).or_default();
+                *entry = (*entry).max(*count);
+            }
+        }
+    }
+}
+
+/// Body exchanged between nodes to converge their counters
+///
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+#[serde(bound(
+    serialize = "A: Serialize, I: Serialize",
+    deserialize = "A: DeserializeOwned, I: DeserializeOwned"
+))]
+pub enum PnCounterBody<A, I>
+where
+    A: Address,
+    I: MessageId,
+{
+    #[serde(rename = "pn_counter_state")]
+    State {
+        #[serde(rename = "msg_id")]
+        message_id: I,
+        counter: PnCounter<A>,
+    },
+}
+
+pub type PnCounterMessage<A, I> = Message<A, PnCounterBody<A, I>, I>;
+
+/// Handles the `pn-counter` workload
+///
+/// Clients use [`CounterBody`], deltas are added to this node's tallies and reach the other nodes
+/// through [`PnCounterHandler::gossip_pn_counter`], so reads are eventually consistent.
+///
+pub trait PnCounterHandler<A, I>:
+    ClusterRegistry<A, I> + MessageIdRegistry<I> + PnCounterRegistry<A>
+where
+    A: Address + DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    fn respond_pn_counter(
+        &mut self,
+        request: CounterBody<I>,
+    ) -> Result<CounterBody<I>, crate::Error<I>> {
+        match request {
+            CounterBody::AddRequest { message_id, delta } => {
+                let node = self.node_id().clone();
+                self.pn_counter().add(node, delta);
+                Ok(CounterBody::AddResponse {
+                    in_reply_to: message_id,
+                    message_id: self.gen_msg_id(),
+                })
+            }
+            CounterBody::ReadRequest { message_id } => Ok(CounterBody::ReadResponse {
+                in_reply_to: message_id,
+                message_id: self.gen_msg_id(),
+                value: self.pn_counter().value(),
+            }),
+            CounterBody::AddResponse { message_id, .. }
+            | CounterBody::ReadResponse { message_id, .. } => Err(crate::Error::new(
+                message_id,
+                Code::MalformedRequest,
+                "Request is response".to_owned(),
+            )),
+        }
+    }
+
+    /// Sends the counter to every peer, to be called periodically
+    fn gossip_pn_counter(&mut self) -> Vec<PnCounterMessage<A, I>> {
+        let counter = self.pn_counter().clone();
+        let source = self.node_id().clone();
+        let mut outgoing = Vec::new();
+        for peer in self.peers() {
+            outgoing.push(Message {
+                source: source.clone(),
+                destination: peer,
+                body: Ok(PnCounterBody::State {
+                    message_id: self.gen_msg_id(),
+                    counter: counter.clone(),
+                }),
+            });
+        }
+        outgoing
+    }
+
+    fn handle_pn_counter(&mut self, body: PnCounterBody<A, I>) {
+        match body {
+            PnCounterBody::State { counter, .. } => self.pn_counter().merge(&counter),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        counter::CounterBody, ClusterRegistry, MessageIdRegistry, NodeIdRegistry, PnCounterRegistry,
+    };
+
+    use super::{PnCounter, PnCounterHandler};
+
+    pub struct TestNode {
+        n: u32,
+        id: String,
+        node_ids: Vec<String>,
+        counter: PnCounter<String>,
+    }
+
+    impl TestNode {
+        fn new(id: &str) -> Self {
+            Self {
+                n: 0,
+                id: id.to_owned(),
+                node_ids: vec!["n1".to_owned(), "n2".to_owned()],
+                counter: PnCounter::default(),
+            }
+        }
+
+        fn read(&mut self) -> i64 {
+            match self.respond_pn_counter(CounterBody::ReadRequest { message_id: 1 }) {
+                Ok(CounterBody::ReadResponse { value, .. }) => value,
+                other => panic!("expected read_ok, got {other:?}"),
+            }
+        }
+    }
+
+    impl MessageIdRegistry<u32> for TestNode {
+        fn gen_msg_id(&mut self) -> u32 {
+            self.n += 1;
+            self.n
+        }
+    }
+
+    impl NodeIdRegistry<String, u32> for TestNode {
+        fn node_id(&self) -> &String {
+            &self.id
+        }
+
+        fn set_node_id(&mut self, id: String) -> Result<(), crate::Error<u32>> {
+            self.id = id;
+            Ok(())
+        }
+    }
+
+    impl ClusterRegistry<String, u32> for TestNode {
+        fn set_node_ids(&mut self, node_ids: Vec<String>) {
+            self.node_ids = node_ids;
+        }
+
+        fn node_ids(&self) -> &[String] {
+            &self.node_ids
+        }
+    }
+
+    impl PnCounterRegistry<String> for TestNode {
+        fn pn_counter(&mut self) -> &mut PnCounter<String> {
+            &mut self.counter
+        }
+    }
+
+    impl PnCounterHandler<String, u32> for TestNode {}
+
+    #[test]
+    fn test_pn_counter_converges() {
+        let mut n1 = TestNode::new("n1");
+        let mut n2 = TestNode::new("n2");
+        let add = |delta| CounterBody::AddRequest {
+            message_id: 1,
+            delta,
+        };
+        n1.respond_pn_counter(add(5)).unwrap();
+        n1.respond_pn_counter(add(-1)).unwrap();
+        n2.respond_pn_counter(add(-3)).unwrap();
+        assert_eq!(n1.read(), 4);
+        assert_eq!(n2.read(), -3);
+
+        let to_n2 = n1.gossip_pn_counter().remove(0);
+        assert_eq!(to_n2.destination, "n2");
+        n2.handle_pn_counter(to_n2.body.clone().unwrap());
+        n2.handle_pn_counter(to_n2.body.unwrap());
+        for message in n2.gossip_pn_counter() {
+            n1.handle_pn_counter(message.body.unwrap());
+        }
+        assert_eq!(n1.read(), 1);
+        assert_eq!(n2.read(), 1);
+    }
+}
@@ -0,0 +1,126 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{error::Code, Address, Message, MessageId};
+
+/// Everything needed to answer a request after its handler has returned
+///
+/// A handle is taken from the request the same way `ResponseBuilder::build_response` addresses
+/// responses and is consumed once the reply is built, so each request is answered at most once.
+///
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ReplyHandle<A: Address, I: MessageId> {
+    source: A,
+    destination: A,
+    in_reply_to: I,
+}
+
+impl<A, I> ReplyHandle<A, I>
+where
+    A: Address,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    /// Captures the addresses and `msg_id` of a request, `None` if it carries no `msg_id`
+    pub fn new<B>(request: &Message<A, B, I>) -> Option<Self>
+    where
+        B: DeserializeOwned + Serialize,
+    {
+        Some(Self {
+            source: request.destination.clone(),
+            destination: request.source.clone(),
+            in_reply_to: request.body_field("msg_id")?,
+        })
+    }
+
+    /// The `msg_id` of the request, to be used as `in_reply_to` in the reply body
+    pub fn in_reply_to(&self) -> &I {
+        &self.in_reply_to
+    }
+
+    /// The node or client waiting for the reply
+    pub fn destination(&self) -> &A {
+        &self.destination
+    }
+
+    pub fn complete<B>(self, body: Result<B, crate::Error<I>>) -> Message<A, B, I>
+    where
+        B: DeserializeOwned + Serialize,
+    {
+        Message {
+            source: self.source,
+            destination: self.destination,
+            body,
+        }
+    }
+
+    pub fn fail<B>(self, code: Code, text: String) -> Message<A, B, I>
+    where
+        B: DeserializeOwned + Serialize,
+    {
+        let error = crate::Error::new(self.in_reply_to.clone(), code, text);
+        self.complete(Err(error))
+    }
+}
+
+/// Stashes replies that are waiting for some event, identified by `K`
+///
+pub trait ReplyRegistry<K, A: Address, I: MessageId> {
+    fn defer(&mut self, key: K, reply: ReplyHandle<A, I>);
+    /// Removes and returns every reply waiting for `key`
+    fn take_replies(&mut self, key: &K) -> Vec<ReplyHandle<A, I>>;
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use crate::{counter::CounterBody, Message};
+
+    use super::{ReplyHandle, ReplyRegistry};
+
+    #[derive(Default)]
+    pub struct TestNode {
+        waiting: HashMap<&'static str, Vec<ReplyHandle<String, u32>>>,
+    }
+
+    impl ReplyRegistry<&'static str, String, u32> for TestNode {
+        fn defer(&mut self, key: &'static str, reply: ReplyHandle<String, u32>) {
+            self.waiting.entry(key).or_default().push(reply);
+        }
+
+        fn take_replies(&mut self, key: &&'static str) -> Vec<ReplyHandle<String, u32>> {
+            self.waiting.remove(key).unwrap_or_default()
+        }
+    }
+
+    #[test]
+    fn test_deferred_reply() {
+        let request = r#"{
+          "src": "c1",
+          "dest": "n1",
+          "body": {
+            "type": "read",
+            "msg_id": 4
+          }
+        } "#;
+        let request: Message<String, CounterBody<u32>, u32> =
+            serde_json::from_str(request).unwrap();
+        let mut test_node = TestNode::default();
+        test_node.defer("counter", ReplyHandle::new(&request).unwrap());
+        assert!(test_node.take_replies(&"other").is_empty());
+
+        let replies: Vec<_> = test_node
+            .take_replies(&"counter")
+            .into_iter()
+            .map(|reply| {
+                let body = CounterBody::ReadResponse {
+                    in_reply_to: *reply.in_reply_to(),
+                    message_id: 9,
+                    value: 42,
+                };
+                reply.complete(Ok(body))
+            })
+            .collect();
+        let expected = r#"{"src":"n1","dest":"c1","body":{"type":"read_ok","in_reply_to":4,"msg_id":9,"value":42}}"#;
+        assert_eq!(serde_json::to_string(&replies[0]).unwrap(), expected);
+    }
+}
@@ -0,0 +1,460 @@
+use std::{
+    cell::RefCell,
+    fmt,
+    io::{self, BufRead, Write},
+    iter,
+};
+
+use serde::{
+    de::{self, value::MapDeserializer, DeserializeOwned},
+    Serialize,
+};
+use serde_json::Value;
+
+use crate::{
+    error::Code,
+    init::{Admission, InitGate},
+    Address, Message, MessageId,
+};
+
+/// Outcome of decoding a single line received from the network
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Received<A, B, I>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    /// A message the node knows how to handle
+    Message(Message<A, B, I>),
+    /// The message can't be handled, the error reply has to be sent back to its source
+    Rejected(Message<A, B, I>),
+    /// The input can't be attributed to a request, so there is nobody to reply to
+    Dropped(String),
+}
+
+/// Decodes a line, answering unknown body types with `NotSupported` and anything else that
+/// doesn't parse with `MalformedRequest`
+///
+/// Replies are only possible when the line carries `src`, `dest` and a `msg_id`.
+pub fn decode<A, B, I>(line: &str) -> Received<A, B, I>
+where
+    A: Address + DeserializeOwned,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    let value: Value = match serde_json::from_str(line) {
+        Ok(value) => value,
+        Err(err) => return Received::Dropped(err.to_string()),
+    };
+    let err = match serde_json::from_value::<Message<A, B, I>>(value.clone()) {
+        Ok(message) => return Received::Message(message),
+        Err(err) => err,
+    };
+    let field = |name: &str| value.get(name).cloned().unwrap_or(Value::Null);
+    let body = field("body");
+    let (Ok(source), Ok(destination), Some(Ok(message_id))) = (
+        serde_json::from_value::<A>(field("src")),
+        serde_json::from_value::<A>(field("dest")),
+        body.get("msg_id")
+            .map(|id| serde_json::from_value::<I>(id.clone())),
+    ) else {
+        return Received::Dropped(err.to_string());
+    };
+    let known = known_types::<B>();
+    let (code, text) = match body.get("type").and_then(Value::as_str) {
+        Some(kind) if !known.is_empty() && !known.contains(&kind) => (
+            Code::NotSupported,
+            format!("Unsupported message type {}", body["type"]),
+        ),
+        Some(kind) => match serde_json::from_value::<B>(body.clone()) {
+            Err(body_err) => (
+                Code::MalformedRequest,
+                format!("Invalid {kind} body: {body_err}"),
+            ),
+            Ok(_) => (Code::MalformedRequest, err.to_string()),
+        },
+        None => (Code::MalformedRequest, "Body has no type".to_owned()),
+    };
+    Received::Rejected(Message {
+        source: destination,
+        destination: source,
+        body: Err(crate::Error::new(message_id, code, text)),
+    })
+}
+
+thread_local! {
+    static KNOWN_TYPES: RefCell<Vec<&'static str>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Error of a probe decode, collecting the variants serde expected in place of an unknown one
+#[derive(Debug)]
+struct TypeProbe;
+
+impl fmt::Display for TypeProbe {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("type probe")
+    }
+}
+
+impl std::error::Error for TypeProbe {}
+
+impl de::Error for TypeProbe {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        TypeProbe
+    }
+
+    fn unknown_variant(_variant: &str, expected: &'static [&'static str]) -> Self {
+        KNOWN_TYPES.with(|known| known.borrow_mut().extend_from_slice(expected));
+        TypeProbe
+    }
+}
+
+/// Body types `B` decodes, empty if they can't be told
+///
+/// Decoding a body with an empty `type` makes tagged enums report their variants, untagged enums
+/// try each of their variants with the same error type so the types of all of them are collected.
+fn known_types<B: DeserializeOwned>() -> Vec<&'static str> {
+    KNOWN_TYPES.with(|known| known.borrow_mut().clear());
+    let probe = MapDeserializer::<_, TypeProbe>::new(iter::once(("type", "")));
+    let _ = B::deserialize(probe);
+    KNOWN_TYPES.with(|known| known.take())
+}
+
+/// A node driven by [`run`], closures handling a single message implement it as well
+///
+pub trait Node<A, B, I>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    fn handle(&mut self, message: Message<A, B, I>) -> Vec<Message<A, B, I>>;
+
+    /// Gate deciding which messages reach [`Node::handle`] before `init`, nodes without one handle
+    /// every message
+    fn gate(&mut self) -> Option<&mut InitGate<A, B, I>> {
+        None
+    }
+
+    /// Called once the input is closed, the returned messages are still sent
+    fn on_shutdown(&mut self) -> Vec<Message<A, B, I>> {
+        Vec::new()
+    }
+}
+
+impl<A, B, I, F> Node<A, B, I> for F
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+    F: FnMut(Message<A, B, I>) -> Vec<Message<A, B, I>>,
+{
+    fn handle(&mut self, message: Message<A, B, I>) -> Vec<Message<A, B, I>> {
+        self(message)
+    }
+}
+
+/// Drives a node over line delimited JSON until `input` is exhausted
+///
+/// Every decoded message is passed to `node` and the messages it returns are written to
+/// `output`, rejected messages are answered without involving `node`. With a [`Node::gate`]
+/// messages are admitted through it first, those it buffered are handled as soon as a message
+/// initializes the gate.
+pub fn run<A, B, I, R, W, N>(input: R, mut output: W, mut node: N) -> io::Result<()>
+where
+    A: Address + DeserializeOwned + Serialize,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+    R: BufRead,
+    W: Write,
+    N: Node<A, B, I>,
+{
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let outgoing = match decode(&line) {
+            Received::Message(message) => dispatch(&mut node, message),
+            Received::Rejected(reply) => vec![reply],
+            Received::Dropped(_) => Vec::new(),
+        };
+        send(&mut output, outgoing)?;
+    }
+    send(&mut output, node.on_shutdown())
+}
+
+fn dispatch<A, B, I, N>(node: &mut N, message: Message<A, B, I>) -> Vec<Message<A, B, I>>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+    N: Node<A, B, I>,
+{
+    let Some(gate) = node.gate() else {
+        return node.handle(message);
+    };
+    let message = match gate.admit(message) {
+        Admission::Handle(message) => message,
+        Admission::Rejected(reply) => return vec![reply],
+        Admission::Buffered | Admission::Dropped => return Vec::new(),
+    };
+    let mut outgoing = node.handle(message);
+    let released = node.gate().map(InitGate::release).unwrap_or_default();
+    for message in released {
+        outgoing.extend(node.handle(message));
+    }
+    outgoing
+}
+
+fn send<A, B, I, W>(output: &mut W, messages: Vec<Message<A, B, I>>) -> io::Result<()>
+where
+    A: Address + Serialize,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+    W: Write,
+{
+    for message in messages {
+        serde_json::to_writer(&mut *output, &message)?;
+        output.write_all(b"\n")?;
+    }
+    output.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::{
+        echo::{EchoBody, EchoHandler},
+        error::Code,
+        init::{InitBody, InitGate, InitHandler, PreInit},
+        ClusterRegistry, InitGateRegistry, Message, MessageIdRegistry, NodeIdRegistry,
+        ResponseBuilder,
+    };
+
+    use serde::{Deserialize, Serialize};
+
+    use super::{decode, known_types, run, Node, Received};
+
+    #[derive(Default)]
+    pub struct TestNode {
+        n: u32,
+    }
+
+    impl MessageIdRegistry<u32> for TestNode {
+        fn gen_msg_id(&mut self) -> u32 {
+            self.n += 1;
+            self.n
+        }
+    }
+
+    impl EchoHandler<String, u32> for TestNode {}
+    impl ResponseBuilder<String, u32, EchoBody<u32>> for TestNode {}
+
+    #[test]
+    fn test_reply_to_unsupported_and_malformed() {
+        let input = [
+            r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"hi"}}"#,
+            r#"{"src":"c1","dest":"n1","body":{"type":"cas","msg_id":2,"key":1}}"#,
+            r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":3}}"#,
+            r#"{"src":"c1","dest":"n1","body":{"type":"cas"}}"#,
+            r#"{"src":"c1","dest":"n1","body":{"type":"read","msg_id":4}}"#,
+            r#"not json"#,
+        ]
+        .join("\n");
+        let expected = [
+            r#"{"src":"n1","dest":"c1","body":{"type":"echo_ok","in_reply_to":1,"msg_id":1,"echo":"hi"}}"#,
+            r#"{"src":"n1","dest":"c1","body":{"type":"error","in_reply_to":2,"code":10,"text":"Unsupported message type \"cas\""}}"#,
+        ];
+        let mut node = TestNode::default();
+        let mut output = Vec::new();
+        run(
+            Cursor::new(input),
+            &mut output,
+            |request: Message<String, EchoBody<u32>, u32>| {
+                let body = request
+                    .body
+                    .clone()
+                    .and_then(|body| node.respond_echo(body));
+                vec![TestNode::build_response(&request, body)]
+            },
+        )
+        .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[..2], expected);
+        assert!(lines[2].contains(
+            r#""in_reply_to":3,"code":12,"text":"Invalid echo body: missing field `echo`""#
+        ));
+        assert!(lines[3].contains(r#""in_reply_to":4,"code":10"#));
+    }
+
+    /// Holds every echo back until the input closes
+    #[derive(Default)]
+    pub struct BatchingNode {
+        held: Vec<Message<String, EchoBody<u32>, u32>>,
+    }
+
+    impl Node<String, EchoBody<u32>, u32> for BatchingNode {
+        fn handle(
+            &mut self,
+            message: Message<String, EchoBody<u32>, u32>,
+        ) -> Vec<Message<String, EchoBody<u32>, u32>> {
+            self.held.push(message);
+            Vec::new()
+        }
+
+        fn on_shutdown(&mut self) -> Vec<Message<String, EchoBody<u32>, u32>> {
+            self.held.drain(..).collect()
+        }
+    }
+
+    #[test]
+    fn test_flush_on_shutdown() {
+        let input = r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"hi"}}"#;
+        let mut output = Vec::new();
+        run(Cursor::new(input), &mut output, BatchingNode::default()).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap().trim(), input);
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(untagged)]
+    pub enum Body {
+        Init(InitBody<u32, String>),
+        Echo(EchoBody<u32>),
+        Level(LevelBody),
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(tag = "type", rename_all = "snake_case")]
+    pub enum LevelBody {
+        SetLevel { msg_id: u32, level: Level },
+    }
+
+    #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+    #[serde(rename_all = "snake_case")]
+    pub enum Level {
+        Low,
+    }
+
+    #[test]
+    fn test_decode_untagged_bodies() {
+        let known = known_types::<Body>();
+        for kind in ["init", "init_ok", "echo", "echo_ok", "set_level"] {
+            assert!(known.contains(&kind), "{kind} missing from {known:?}");
+        }
+
+        let code = |line: &str| match decode::<String, Body, u32>(line) {
+            Received::Rejected(reply) => Some(reply.body.unwrap_err().code()),
+            _ => None,
+        };
+        let nested =
+            r#"{"src":"c1","dest":"n1","body":{"type":"set_level","msg_id":1,"level":"high"}}"#;
+        assert_eq!(code(nested), Some(Code::MalformedRequest));
+        let unknown = r#"{"src":"c1","dest":"n1","body":{"type":"topology","msg_id":1}}"#;
+        assert_eq!(code(unknown), Some(Code::NotSupported));
+    }
+
+    /// Echo node that buffers requests until `init`
+    pub struct GatedNode {
+        n: u32,
+        id: String,
+        node_ids: Vec<String>,
+        gate: InitGate<String, Body, u32>,
+    }
+
+    impl MessageIdRegistry<u32> for GatedNode {
+        fn gen_msg_id(&mut self) -> u32 {
+            self.n += 1;
+            self.n
+        }
+    }
+
+    impl NodeIdRegistry<String, u32> for GatedNode {
+        fn node_id(&self) -> &String {
+            &self.id
+        }
+
+        fn set_node_id(&mut self, id: String) -> Result<(), crate::Error<u32>> {
+            self.id = id;
+            Ok(())
+        }
+    }
+
+    impl ClusterRegistry<String, u32> for GatedNode {
+        fn set_node_ids(&mut self, node_ids: Vec<String>) {
+            self.node_ids = node_ids;
+        }
+
+        fn node_ids(&self) -> &[String] {
+            &self.node_ids
+        }
+    }
+
+    impl InitGateRegistry<String, Body, u32> for GatedNode {
+        fn init_gate(&mut self) -> &mut InitGate<String, Body, u32> {
+            &mut self.gate
+        }
+    }
+
+    impl InitHandler<String, u32, Body> for GatedNode {}
+    impl EchoHandler<String, u32> for GatedNode {}
+
+    impl Node<String, Body, u32> for GatedNode {
+        fn handle(
+            &mut self,
+            message: Message<String, Body, u32>,
+        ) -> Vec<Message<String, Body, u32>> {
+            let (mut outgoing, body) = match message.body.clone() {
+                Ok(Body::Init(body)) => match self.respond_init(body) {
+                    Ok(init) => (init.peers, Ok(Body::Init(init.response))),
+                    Err(err) => (Vec::new(), Err(err)),
+                },
+                Ok(Body::Echo(body)) => (Vec::new(), self.respond_echo(body).map(Body::Echo)),
+                _ => return Vec::new(),
+            };
+            outgoing.insert(
+                0,
+                Message {
+                    source: message.destination,
+                    destination: message.source,
+                    body,
+                },
+            );
+            outgoing
+        }
+
+        fn gate(&mut self) -> Option<&mut InitGate<String, Body, u32>> {
+            Some(&mut self.gate)
+        }
+    }
+
+    #[test]
+    fn test_run_releases_buffered_messages_after_init() {
+        let input = [
+            r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":1,"echo":"early"}}"#,
+            r#"{"src":"c0","dest":"n1","body":{"type":"init","msg_id":1,"node_id":"n1","node_ids":["n1"]}}"#,
+            r#"{"src":"c1","dest":"n1","body":{"type":"echo","msg_id":2,"echo":"late"}}"#,
+        ]
+        .join("\n");
+        let node = GatedNode {
+            n: 0,
+            id: String::new(),
+            node_ids: Vec::new(),
+            gate: InitGate::new(PreInit::Buffer { capacity: 4 }),
+        };
+        let mut output = Vec::new();
+        run(Cursor::new(input), &mut output, node).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let expected = [
+            r#"{"src":"n1","dest":"c0","body":{"type":"init_ok","in_reply_to":1}}"#,
+            r#"{"src":"n1","dest":"c1","body":{"type":"echo_ok","in_reply_to":1,"msg_id":1,"echo":"early"}}"#,
+            r#"{"src":"n1","dest":"c1","body":{"type":"echo_ok","in_reply_to":2,"msg_id":2,"echo":"late"}}"#,
+        ];
+        assert_eq!(output.lines().collect::<Vec<_>>(), expected);
+    }
+}
@@ -0,0 +1,315 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    time::{Duration, Instant},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{take_field, Address, Message, MessageId};
+
+/// Which part of the network a message travelled through
+///
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Traffic {
+    /// Exchanged between a client and a node
+    Client,
+    /// Exchanged between nodes or between a node and a service
+    Server,
+}
+
+impl<A, B, I> Message<A, B, I>
+where
+    A: Address,
+    B: DeserializeOwned + Serialize,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    /// Classifies the message the same way Maelstrom's network statistics do
+    pub fn traffic(&self) -> Traffic {
+        if self.source.is_client() || self.destination.is_client() {
+            Traffic::Client
+        } else {
+            Traffic::Server
+        }
+    }
+}
+
+/// Latency samples for a single kind of operation
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Histogram {
+    samples: Vec<Duration>,
+}
+
+impl Histogram {
+    pub fn record(&mut self, latency: Duration) {
+        self.samples.push(latency);
+    }
+
+    pub fn count(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.samples.iter().max().copied()
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        let total: Duration = self.samples.iter().sum();
+        (!self.samples.is_empty()).then(|| total / self.samples.len() as u32)
+    }
+
+    /// Nearest-rank percentile, `p` is clamped to `0.0..=1.0`
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+        let rank = (p.clamp(0.0, 1.0) * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.saturating_sub(1)])
+    }
+
+    /// Sample counts grouped into power-of-two millisecond buckets, keyed by upper bound
+    pub fn buckets(&self) -> BTreeMap<Duration, usize> {
+        let mut buckets = BTreeMap::new();
+        for sample in &self.samples {
+            let millis = sample.as_millis().max(1) as u64;
+            let bound = Duration::from_millis(millis.next_power_of_two());
+            *buckets.entry(bound).or_insert(0) += 1;
+        }
+        buckets
+    }
+}
+
+/// Collects message counts and operation latencies for every message observed on the network
+///
+/// Operations are client requests, they are matched with their reply using the client address and
+/// the `msg_id`/`in_reply_to` pair.
+///
+#[derive(Clone, Debug)]
+pub struct Stats<A: Address, I: MessageId> {
+    counts: HashMap<(Traffic, String), usize>,
+    pending: HashMap<(A, I), (String, Instant)>,
+    latencies: HashMap<String, Histogram>,
+}
+
+impl<A: Address, I: MessageId> Default for Stats<A, I> {
+    fn default() -> Self {
+        Self {
+            counts: HashMap::new(),
+            pending: HashMap::new(),
+            latencies: HashMap::new(),
+        }
+    }
+}
+
+impl<A, I> Stats<A, I>
+where
+    A: Address,
+    I: MessageId + DeserializeOwned + Serialize,
+{
+    /// Records a message that was put on the network at the given instant
+    pub fn observe<B>(&mut self, message: &Message<A, B, I>, at: Instant)
+    where
+        B: DeserializeOwned + Serialize,
+    {
+        let mut body = message.body_value().unwrap_or_default();
+        let kind = take_field::<String>(&mut body, "type").unwrap_or_else(|| "unknown".to_owned());
+
+        if message.source.is_client() {
+            if let Some(msg_id) = take_field::<I>(&mut body, "msg_id") {
+                self.pending
+                    .insert((message.source.clone(), msg_id), (kind.clone(), at));
+            }
+        } else if message.destination.is_client() {
+            let request = take_field::<I>(&mut body, "in_reply_to")
+                .and_then(|id| self.pending.remove(&(message.destination.clone(), id)));
+            if let Some((operation, started)) = request {
+                self.latencies
+                    .entry(operation)
+                    .or_default()
+                    .record(at.saturating_duration_since(started));
+            }
+        }
+        *self.counts.entry((message.traffic(), kind)).or_insert(0) += 1;
+    }
+
+    /// Number of observed messages of the given `type`
+    pub fn count(&self, traffic: Traffic, kind: &str) -> usize {
+        self.counts
+            .get(&(traffic, kind.to_owned()))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Number of observed messages of any type
+    pub fn total(&self, traffic: Traffic) -> usize {
+        self.counts
+            .iter()
+            .filter(|((t, _), _)| *t == traffic)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// Number of client operations that have received a reply
+    pub fn operations(&self) -> usize {
+        self.latencies.values().map(Histogram::count).sum()
+    }
+
+    pub fn latency(&self, operation: &str) -> Option<&Histogram> {
+        self.latencies.get(operation)
+    }
+
+    pub fn report(&self) -> Report {
+        let operations = self.operations();
+        let server_messages = self.total(Traffic::Server);
+        Report {
+            client_messages: self.total(Traffic::Client),
+            server_messages,
+            operations,
+            msgs_per_op: match operations {
+                0 => 0.0,
+                n => server_messages as f64 / n as f64,
+            },
+            messages: self
+                .counts
+                .iter()
+                .map(|((traffic, kind), count)| ((*traffic, kind.clone()), *count))
+                .collect(),
+            latencies: self
+                .latencies
+                .iter()
+                .map(|(operation, histogram)| (operation.clone(), histogram.into()))
+                .collect(),
+        }
+    }
+}
+
+/// Latency percentiles for a single kind of operation
+///
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub median: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl From<&Histogram> for LatencySummary {
+    fn from(histogram: &Histogram) -> Self {
+        let percentile = |p| histogram.percentile(p).unwrap_or_default();
+        Self {
+            count: histogram.count(),
+            median: percentile(0.5),
+            p95: percentile(0.95),
+            p99: percentile(0.99),
+            max: histogram.max().unwrap_or_default(),
+        }
+    }
+}
+
+/// Summary of a run, `msgs_per_op` follows Maelstrom and only counts server traffic
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Report {
+    pub client_messages: usize,
+    pub server_messages: usize,
+    pub operations: usize,
+    pub msgs_per_op: f64,
+    pub messages: BTreeMap<(Traffic, String), usize>,
+    pub latencies: BTreeMap<String, LatencySummary>,
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "client messages: {}", self.client_messages)?;
+        writeln!(f, "server messages: {}", self.server_messages)?;
+        writeln!(f, "operations:      {}", self.operations)?;
+        writeln!(f, "msgs-per-op:     {:.3}", self.msgs_per_op)?;
+        for ((traffic, kind), count) in &self.messages {
+            writeln!(f, "  {:?} {}: {}", traffic, kind, count)?;
+        }
+        for (operation, summary) in &self.latencies {
+            writeln!(
+                f,
+                "  {} latency: n={} median={:?} p95={:?} p99={:?} max={:?}",
+                operation, summary.count, summary.median, summary.p95, summary.p99, summary.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{echo::EchoBody, Message};
+
+    use super::{Histogram, Stats, Traffic};
+
+    type EchoMessage = Message<String, EchoBody<u32>, u32>;
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let mut histogram = Histogram::default();
+        for millis in 1..=100 {
+            histogram.record(Duration::from_millis(millis));
+        }
+        assert_eq!(histogram.percentile(0.5), Some(Duration::from_millis(50)));
+        assert_eq!(histogram.percentile(0.99), Some(Duration::from_millis(99)));
+        assert_eq!(histogram.max(), Some(Duration::from_millis(100)));
+        assert_eq!(
+            histogram.buckets().get(&Duration::from_millis(128)),
+            Some(&36)
+        );
+    }
+
+    #[test]
+    fn test_stats_report() {
+        let start = Instant::now();
+        let request: EchoMessage = Message {
+            source: "c1".to_owned(),
+            destination: "n1".to_owned(),
+            body: Ok(EchoBody::Request {
+                message_id: 1,
+                echo: "hello".to_owned(),
+            }),
+        };
+        let gossip: EchoMessage = Message {
+            source: "n1".to_owned(),
+            destination: "n2".to_owned(),
+            body: Ok(EchoBody::Request {
+                message_id: 7,
+                echo: "hello".to_owned(),
+            }),
+        };
+        let response: EchoMessage = Message {
+            source: "n1".to_owned(),
+            destination: "c1".to_owned(),
+            body: Ok(EchoBody::Response {
+                in_reply_to: 1,
+                message_id: 8,
+                echo: "hello".to_owned(),
+            }),
+        };
+        let mut stats = Stats::default();
+        stats.observe(&request, start);
+        stats.observe(&gossip, start + Duration::from_millis(1));
+        stats.observe(&gossip, start + Duration::from_millis(2));
+        stats.observe(&response, start + Duration::from_millis(5));
+
+        assert_eq!(stats.count(Traffic::Client, "echo"), 1);
+        assert_eq!(stats.count(Traffic::Server, "echo"), 2);
+        assert_eq!(stats.operations(), 1);
+        assert_eq!(
+            stats.latency("echo").unwrap().percentile(0.5),
+            Some(Duration::from_millis(5))
+        );
+        let report = stats.report();
+        assert_eq!(report.client_messages, 2);
+        assert_eq!(report.msgs_per_op, 2.0);
+    }
+}
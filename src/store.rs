@@ -0,0 +1,218 @@
+use std::{
+    collections::{BTreeSet, HashSet},
+    hash::Hash,
+    sync::{Arc, OnceLock},
+};
+
+use indexmap::IndexSet;
+
+/// Set of the values a node has seen
+///
+pub trait MessageStore<T> {
+    /// Stores `value`, `true` if it wasn't stored before
+    fn insert(&mut self, value: T) -> bool;
+
+    fn contains(&self, value: &T) -> bool;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a T>
+    where
+        T: 'a;
+
+    /// Every value in iteration order, shared between reads until the next insert
+    fn snapshot(&self) -> Arc<[T]>;
+
+    /// Up to `limit` values in insertion order, starting with the `version`th value inserted
+    ///
+    /// `None` if the store doesn't keep insertion order. Values are never removed, so a version
+    /// stays valid as the store grows.
+    fn since(&self, version: usize, limit: usize) -> Option<Vec<T>>;
+}
+
+/// Set implementation backing a [`MessageSet`]
+///
+pub trait SetBackend<T>: Default {
+    /// Whether [`SetBackend::get_index`] returns values in insertion order
+    const INSERTION_ORDERED: bool = false;
+
+    fn insert(&mut self, value: T) -> bool;
+    fn contains(&self, value: &T) -> bool;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a T>
+    where
+        T: 'a;
+    fn get_index(&self, _index: usize) -> Option<&T> {
+        None
+    }
+}
+
+impl<T: Eq + Hash> SetBackend<T> for HashSet<T> {
+    fn insert(&mut self, value: T) -> bool {
+        HashSet::insert(self, value)
+    }
+    fn contains(&self, value: &T) -> bool {
+        HashSet::contains(self, value)
+    }
+    fn len(&self) -> usize {
+        HashSet::len(self)
+    }
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a T>
+    where
+        T: 'a,
+    {
+        HashSet::iter(self)
+    }
+}
+
+impl<T: Ord> SetBackend<T> for BTreeSet<T> {
+    fn insert(&mut self, value: T) -> bool {
+        BTreeSet::insert(self, value)
+    }
+    fn contains(&self, value: &T) -> bool {
+        BTreeSet::contains(self, value)
+    }
+    fn len(&self) -> usize {
+        BTreeSet::len(self)
+    }
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a T>
+    where
+        T: 'a,
+    {
+        BTreeSet::iter(self)
+    }
+}
+
+impl<T: Eq + Hash> SetBackend<T> for IndexSet<T> {
+    const INSERTION_ORDERED: bool = true;
+
+    fn insert(&mut self, value: T) -> bool {
+        IndexSet::insert(self, value)
+    }
+    fn contains(&self, value: &T) -> bool {
+        IndexSet::contains(self, value)
+    }
+    fn len(&self) -> usize {
+        IndexSet::len(self)
+    }
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a T>
+    where
+        T: 'a,
+    {
+        IndexSet::iter(self)
+    }
+    fn get_index(&self, index: usize) -> Option<&T> {
+        IndexSet::get_index(self, index)
+    }
+}
+
+/// [`MessageStore`] over a set backend, caching the snapshot handed out to reads
+///
+#[derive(Clone, Debug)]
+pub struct MessageSet<S, T> {
+    values: S,
+    snapshot: OnceLock<Arc<[T]>>,
+}
+
+/// Unordered values
+pub type HashMessages<T> = MessageSet<HashSet<T>, T>;
+/// Values in ascending order
+pub type BTreeMessages<T> = MessageSet<BTreeSet<T>, T>;
+/// Values in insertion order
+pub type IndexMessages<T> = MessageSet<IndexSet<T>, T>;
+
+impl<S: Default, T> Default for MessageSet<S, T> {
+    fn default() -> Self {
+        Self {
+            values: S::default(),
+            snapshot: OnceLock::new(),
+        }
+    }
+}
+
+impl<S: SetBackend<T>, T: Clone> MessageStore<T> for MessageSet<S, T> {
+    fn insert(&mut self, value: T) -> bool {
+        let inserted = self.values.insert(value);
+        if inserted {
+            self.snapshot.take();
+        }
+        inserted
+    }
+
+    fn contains(&self, value: &T) -> bool {
+        self.values.contains(value)
+    }
+
+    fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a T>
+    where
+        T: 'a,
+    {
+        self.values.iter()
+    }
+
+    fn snapshot(&self) -> Arc<[T]> {
+        self.snapshot
+            .get_or_init(|| self.values.iter().cloned().collect())
+            .clone()
+    }
+
+    fn since(&self, version: usize, limit: usize) -> Option<Vec<T>> {
+        if !S::INSERTION_ORDERED {
+            return None;
+        }
+        let end = self.values.len().min(version.saturating_add(limit));
+        Some(
+            (version..end)
+                .filter_map(|index| self.values.get_index(index))
+                .cloned()
+                .collect(),
+        )
+    }
+}
+
+impl<S: SetBackend<T>, T: Clone> FromIterator<T> for MessageSet<S, T> {
+    fn from_iter<It: IntoIterator<Item = T>>(iter: It) -> Self {
+        let mut set = Self::default();
+        for value in iter {
+            set.insert(value);
+        }
+        set
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{BTreeMessages, HashMessages, IndexMessages, MessageStore};
+
+    #[test]
+    fn test_message_sets() {
+        let mut index = IndexMessages::default();
+        assert!(index.insert(3));
+        assert!(index.insert(1));
+        assert!(!index.insert(3));
+        let snapshot = index.snapshot();
+        assert_eq!(*snapshot, [3, 1]);
+        assert!(Arc::ptr_eq(&snapshot, &index.snapshot()));
+        index.insert(2);
+        assert_eq!(*index.snapshot(), [3, 1, 2]);
+
+        let btree: BTreeMessages<u32> = [3, 1, 3, 2].into_iter().collect();
+        assert_eq!(*btree.snapshot(), [1, 2, 3]);
+        let hash: HashMessages<u32> = [3, 1, 3, 2].into_iter().collect();
+        assert_eq!(hash.len(), 3);
+        assert!(hash.contains(&2));
+    }
+}
@@ -1,15 +1,17 @@
 use std::collections::HashMap;
 
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
-use crate::{error::Code, Address, MessageId, MessageIndex, NodeId, TopologyRegistry};
+use crate::{
+    error::Code, Address, Message, MessageId, MessageIdRegistry, NodeIdRegistry, TopologyRegistry,
+};
 
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(tag = "type")]
-pub enum TopologyBody<I: MessageIndex, A: Address>
+pub enum TopologyBody<I: MessageId, A: Address>
 where
     A: Address,
-    I: MessageIndex,
+    I: MessageId,
 {
     #[serde(rename = "topology")]
     Request {
@@ -25,26 +27,55 @@ where
     },
 }
 
+/// Reply to `topology` plus the messages started by [`TopologyHandler::on_topology`]
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Connected<A, I, B>
+where
+    A: Address,
+    I: MessageId + DeserializeOwned + Serialize,
+    B: DeserializeOwned + Serialize,
+{
+    pub response: TopologyBody<I, A>,
+    pub peers: Vec<Message<A, B, I>>,
+}
+
 /// This trait has to be implement for every Node alongside any workload specific functionality
 ///
-pub trait TopologyHandler<A, I>: MessageId<I> + NodeId<A, I> + TopologyRegistry<A>
+/// `B` is the body of the messages [`TopologyHandler::on_topology`] sends to the new neighbours.
+///
+pub trait TopologyHandler<A, I, B = TopologyBody<I, A>>:
+    MessageIdRegistry<I> + NodeIdRegistry<A, I> + TopologyRegistry<A>
 where
     A: Address,
-    I: MessageIndex,
+    I: MessageId + DeserializeOwned + Serialize,
+    B: DeserializeOwned + Serialize,
 {
+    /// Called once the neighbours are stored, the returned messages are sent along with
+    /// `topology_ok`
+    fn on_topology(&mut self) -> Vec<Message<A, B, I>> {
+        Vec::new()
+    }
+
+    /// Stores the neighbours of this node, nodes left out of the topology get none
     fn respond(
         &mut self,
         request: TopologyBody<I, A>,
-    ) -> Result<TopologyBody<I, A>, crate::Error<I>> {
+    ) -> Result<Connected<A, I, B>, crate::Error<I>> {
         match request {
             TopologyBody::Request {
                 message_id,
-                topology,
+                mut topology,
             } => {
-                self.set_topology(topology.get(self.node_id()).unwrap().clone());
-                Ok(TopologyBody::Response {
-                    in_reply_to: message_id,
-                    message_id: self.gen_msg_id(),
+                let neighbours = topology.remove(self.node_id()).unwrap_or_default();
+                self.set_topology(neighbours);
+                let peers = self.on_topology();
+                Ok(Connected {
+                    response: TopologyBody::Response {
+                        in_reply_to: message_id,
+                        message_id: self.gen_msg_id(),
+                    },
+                    peers,
                 })
             }
             TopologyBody::Response { message_id, .. } => Err(crate::Error::new(
@@ -58,7 +89,9 @@ where
 
 #[cfg(test)]
 mod test {
-    use crate::{Address, Message, MessageId, NodeId, ResponseBuilder, TopologyRegistry};
+    use crate::{
+        Address, Message, MessageIdRegistry, NodeIdRegistry, ResponseBuilder, TopologyRegistry,
+    };
 
     use super::{TopologyBody, TopologyHandler};
 
@@ -67,16 +100,17 @@ mod test {
         n: u32,
         topology: Vec<A>,
         id: A,
+        updates: usize,
     }
 
-    impl MessageId<u32> for TestNode<String> {
+    impl MessageIdRegistry<u32> for TestNode<String> {
         fn gen_msg_id(&mut self) -> u32 {
             self.n += 1;
             self.n
         }
     }
 
-    impl NodeId<String, u32> for TestNode<String> {
+    impl NodeIdRegistry<String, u32> for TestNode<String> {
         fn node_id(&self) -> &String {
             &self.id
         }
@@ -87,12 +121,20 @@ mod test {
         }
     }
 
-    impl TopologyHandler<String, u32> for TestNode<String> {}
+    impl TopologyHandler<String, u32> for TestNode<String> {
+        fn on_topology(&mut self) -> Vec<Message<String, TopologyBody<u32, String>, u32>> {
+            self.updates += 1;
+            Vec::new()
+        }
+    }
     impl ResponseBuilder<String, u32, TopologyBody<u32, String>> for TestNode<String> {}
     impl TopologyRegistry<String> for TestNode<String> {
         fn set_topology(&mut self, topology: Vec<String>) {
             self.topology = topology;
         }
+        fn topology(&self) -> &[String] {
+            self.topology.as_slice()
+        }
     }
 
     #[test]
@@ -114,6 +156,7 @@ mod test {
             n: 0,
             topology: Vec::new(),
             id: "n2".to_owned(),
+            updates: 0,
         };
         let expected =
             r#"{"src":"n1","dest":"c1","body":{"type":"topology_ok","in_reply_to":1,"msg_id":1}}"#;
@@ -122,9 +165,26 @@ mod test {
         let response_body = request
             .body
             .clone()
-            .and_then(|body| test_node.respond(body));
+            .and_then(|body| test_node.respond(body))
+            .map(|connected| connected.response);
         let response = TestNode::build_response(&request, response_body);
         let res = serde_json::to_string(&response).unwrap();
         assert_eq!(expected, res);
     }
+
+    #[test]
+    fn test_topology_without_this_node() {
+        let mut test_node = TestNode {
+            id: "n4".to_owned(),
+            topology: vec!["n1".to_owned()],
+            ..Default::default()
+        };
+        let request = TopologyBody::Request {
+            message_id: 1,
+            topology: [("n1".to_owned(), vec!["n2".to_owned()])].into(),
+        };
+        test_node.respond(request).unwrap();
+        assert!(test_node.topology().is_empty());
+        assert_eq!(test_node.updates, 1);
+    }
 }
@@ -0,0 +1,455 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap},
+    hash::{BuildHasher, Hasher},
+    time::{Duration, Instant},
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    broadcast::BroadcastBody,
+    counter::CounterBody,
+    echo::EchoBody,
+    generate::GenerateBody,
+    kafka::KafkaBody,
+    list_append::{MicroOp, TxnBody},
+    Address, Message, MessageId,
+};
+
+/// Configuration a workload or [`Generator`] can't run with
+///
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ConfigError {
+    #[error("workload needs at least one key")]
+    NoKeys,
+    #[error("generator needs at least one node")]
+    NoNodes,
+}
+
+/// Small xorshift generator, good enough to vary workloads reproducibly
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    /// Seeds from the random keys the standard library draws for every `RandomState`
+    pub fn from_entropy() -> Self {
+        Self::new(RandomState::new().build_hasher().finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform value in `0..bound`, `bound` has to be non-zero
+    pub fn below(&mut self, bound: u64) -> u64 {
+        self.next_u64() % bound
+    }
+}
+
+/// Produces the request bodies a single kind of client issues
+///
+pub trait Workload<I: MessageId + DeserializeOwned + Serialize> {
+    type Body: DeserializeOwned + Serialize;
+
+    fn request(&mut self, message_id: I, rng: &mut Rng) -> Self::Body;
+}
+
+/// Sends `echo` requests with a distinct payload each time
+///
+#[derive(Clone, Debug, Default)]
+pub struct EchoWorkload {
+    sent: u64,
+}
+
+impl<I: MessageId + DeserializeOwned + Serialize> Workload<I> for EchoWorkload {
+    type Body = EchoBody<I>;
+
+    fn request(&mut self, message_id: I, _rng: &mut Rng) -> Self::Body {
+        self.sent += 1;
+        EchoBody::Request {
+            message_id,
+            echo: format!("Please echo {}", self.sent),
+        }
+    }
+}
+
+/// Sends `generate` requests
+///
+#[derive(Clone, Debug, Default)]
+pub struct GenerateWorkload;
+
+impl<I: MessageId + DeserializeOwned + Serialize> Workload<I> for GenerateWorkload {
+    type Body = GenerateBody<I>;
+
+    fn request(&mut self, message_id: I, _rng: &mut Rng) -> Self::Body {
+        GenerateBody::Request { message_id }
+    }
+}
+
+/// Mixes `broadcast` of unique values with `read`, `read_ratio` is the share of reads
+///
+#[derive(Clone, Debug)]
+pub struct BroadcastWorkload {
+    read_ratio: f64,
+    next_value: u64,
+}
+
+impl BroadcastWorkload {
+    pub fn new(read_ratio: f64) -> Self {
+        Self {
+            read_ratio,
+            next_value: 0,
+        }
+    }
+}
+
+impl<I: MessageId + DeserializeOwned + Serialize> Workload<I> for BroadcastWorkload {
+    type Body = BroadcastBody<I, u64>;
+
+    fn request(&mut self, message_id: I, rng: &mut Rng) -> Self::Body {
+        if (rng.below(1000) as f64) < self.read_ratio * 1000.0 {
+            BroadcastBody::ReadRequest {
+                message_id,
+                since: None,
+                limit: None,
+            }
+        } else {
+            self.next_value += 1;
+            BroadcastBody::PushRequest {
+                message_id,
+                message: self.next_value,
+            }
+        }
+    }
+}
+
+/// Mixes counter `add` and `read`, deltas are drawn from `-max_delta..=max_delta` when
+/// `allow_negative` is set and from `0..=max_delta` otherwise
+///
+/// `max_delta` is capped at `i64::MAX`.
+///
+#[derive(Clone, Debug)]
+pub struct CounterWorkload {
+    read_ratio: f64,
+    max_delta: u64,
+    allow_negative: bool,
+}
+
+impl CounterWorkload {
+    pub fn new(read_ratio: f64, max_delta: u64, allow_negative: bool) -> Self {
+        Self {
+            read_ratio,
+            max_delta: max_delta.min(i64::MAX as u64),
+            allow_negative,
+        }
+    }
+}
+
+impl<I: MessageId + DeserializeOwned + Serialize> Workload<I> for CounterWorkload {
+    type Body = CounterBody<I>;
+
+    fn request(&mut self, message_id: I, rng: &mut Rng) -> Self::Body {
+        if (rng.below(1000) as f64) < self.read_ratio * 1000.0 {
+            return CounterBody::ReadRequest { message_id };
+        }
+        let magnitude = rng.below(self.max_delta + 1) as i64;
+        let delta = if self.allow_negative && rng.below(2) == 0 {
+            -magnitude
+        } else {
+            magnitude
+        };
+        CounterBody::AddRequest { message_id, delta }
+    }
+}
+
+/// Mixes kafka `send` to a random key with `poll` from the start of a random key
+///
+#[derive(Clone, Debug)]
+pub struct KafkaWorkload {
+    keys: Vec<String>,
+    poll_ratio: f64,
+    next_value: u64,
+}
+
+impl KafkaWorkload {
+    pub fn new(keys: Vec<String>, poll_ratio: f64) -> Result<Self, ConfigError> {
+        if keys.is_empty() {
+            return Err(ConfigError::NoKeys);
+        }
+        Ok(Self {
+            keys,
+            poll_ratio,
+            next_value: 0,
+        })
+    }
+}
+
+impl<I: MessageId + DeserializeOwned + Serialize> Workload<I> for KafkaWorkload {
+    type Body = KafkaBody<I, u64>;
+
+    fn request(&mut self, message_id: I, rng: &mut Rng) -> Self::Body {
+        let key = self.keys[rng.below(self.keys.len() as u64) as usize].clone();
+        if (rng.below(1000) as f64) < self.poll_ratio * 1000.0 {
+            KafkaBody::PollRequest {
+                message_id,
+                offsets: HashMap::from([(key, 0)]),
+            }
+        } else {
+            self.next_value += 1;
+            KafkaBody::SendRequest {
+                message_id,
+                key,
+                msg: self.next_value,
+            }
+        }
+    }
+}
+
+/// Sends `txn` requests of `1..=max_ops` micro-ops on keys drawn from `0..keys`
+///
+/// Half the micro-ops are reads. Every append writes a value never appended before, which is what
+/// Elle needs to infer the order of transactions from the lists they read.
+///
+#[derive(Clone, Debug)]
+pub struct ListAppendWorkload {
+    keys: u64,
+    max_ops: u64,
+    next_value: u64,
+}
+
+impl ListAppendWorkload {
+    pub fn new(keys: u64, max_ops: u64) -> Self {
+        Self {
+            keys: keys.max(1),
+            max_ops: max_ops.max(1),
+            next_value: 0,
+        }
+    }
+}
+
+impl<I: MessageId + DeserializeOwned + Serialize> Workload<I> for ListAppendWorkload {
+    type Body = TxnBody<I, u64, u64>;
+
+    fn request(&mut self, message_id: I, rng: &mut Rng) -> Self::Body {
+        let ops = 1 + rng.below(self.max_ops);
+        let txn = (0..ops)
+            .map(|_| {
+                let key = rng.below(self.keys);
+                if rng.below(2) == 0 {
+                    MicroOp::Read(key, None)
+                } else {
+                    self.next_value += 1;
+                    MicroOp::Append(key, self.next_value)
+                }
+            })
+            .collect();
+        TxnBody::Request { message_id, txn }
+    }
+}
+
+/// Mirrors Maelstrom's `--rate`, `--concurrency` and `--timeout` options
+///
+/// A non-positive `rate` issues requests as fast as clients become idle.
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeneratorConfig {
+    pub rate: f64,
+    pub concurrency: usize,
+    pub timeout: Duration,
+    pub seed: u64,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            rate: 5.0,
+            concurrency: 1,
+            timeout: Duration::from_secs(5),
+            seed: 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Client<A: Address> {
+    address: A,
+    node: A,
+    message_id: u32,
+    sent: Option<Instant>,
+}
+
+/// Issues workload requests from a fixed pool of clients
+///
+/// Like Maelstrom clients every client has at most one request in flight, so `concurrency` bounds
+/// the number of outstanding operations. Client `i` always talks to node `i % nodes.len()`.
+///
+#[derive(Clone, Debug)]
+pub struct Generator<A: Address, W> {
+    workload: W,
+    clients: Vec<Client<A>>,
+    interval: Option<Duration>,
+    timeout: Duration,
+    next_due: Option<Instant>,
+    next_id: u32,
+    cursor: usize,
+    rng: Rng,
+}
+
+impl<A, W> Generator<A, W>
+where
+    A: Address + From<String>,
+{
+    pub fn new(workload: W, nodes: &[A], config: GeneratorConfig) -> Result<Self, ConfigError> {
+        if nodes.is_empty() {
+            return Err(ConfigError::NoNodes);
+        }
+        let clients = (0..config.concurrency)
+            .map(|i| Client {
+                address: A::from(format!("c{}", i + 1)),
+                node: nodes[i % nodes.len()].clone(),
+                message_id: 0,
+                sent: None,
+            })
+            .collect();
+        Ok(Self {
+            workload,
+            clients,
+            interval: (config.rate > 0.0).then(|| Duration::from_secs_f64(1.0 / config.rate)),
+            timeout: config.timeout,
+            next_due: None,
+            next_id: 0,
+            cursor: 0,
+            rng: Rng::new(config.seed),
+        })
+    }
+}
+
+impl<A: Address, W> Generator<A, W> {
+    /// Returns the next request if the rate allows it and some client is idle
+    pub fn poll<I>(&mut self, now: Instant) -> Option<Message<A, W::Body, I>>
+    where
+        I: MessageId + DeserializeOwned + Serialize + From<u32>,
+        W: Workload<I>,
+    {
+        if self.next_due.is_some_and(|due| now < due) {
+            return None;
+        }
+        let timeout = self.timeout;
+        let idle = (0..self.clients.len())
+            .map(|offset| (self.cursor + offset) % self.clients.len())
+            .find(|&i| {
+                self.clients[i]
+                    .sent
+                    .is_none_or(|sent| now.saturating_duration_since(sent) >= timeout)
+            })?;
+        self.cursor = (idle + 1) % self.clients.len();
+        self.next_id += 1;
+        self.next_due = self.interval.map(|interval| now + interval);
+
+        let client = &mut self.clients[idle];
+        client.sent = Some(now);
+        client.message_id = self.next_id;
+        Some(Message {
+            source: client.address.clone(),
+            destination: client.node.clone(),
+            body: Ok(self.workload.request(I::from(self.next_id), &mut self.rng)),
+        })
+    }
+
+    /// Frees the client a reply is addressed to, replies to requests that timed out are ignored
+    pub fn complete<B, I>(&mut self, reply: &Message<A, B, I>)
+    where
+        B: DeserializeOwned + Serialize,
+        I: MessageId + DeserializeOwned + Serialize + From<u32>,
+    {
+        let Some(in_reply_to) = reply.body_field::<I>("in_reply_to") else {
+            return;
+        };
+        if let Some(client) = self.clients.iter_mut().find(|client| {
+            client.address == reply.destination && I::from(client.message_id) == in_reply_to
+        }) {
+            client.sent = None;
+        }
+    }
+
+    /// Number of requests still waiting for a reply
+    pub fn in_flight(&self) -> usize {
+        self.clients.iter().filter(|c| c.sent.is_some()).count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::{echo::EchoBody, Message};
+
+    use super::{ConfigError, EchoWorkload, Generator, GeneratorConfig, KafkaWorkload};
+
+    #[test]
+    fn test_generator_rate_and_concurrency() {
+        let nodes = ["n1".to_owned(), "n2".to_owned()];
+        let config = GeneratorConfig {
+            rate: 10.0,
+            concurrency: 2,
+            ..Default::default()
+        };
+        let mut generator = Generator::new(EchoWorkload::default(), &nodes, config).unwrap();
+        let start = Instant::now();
+
+        let first: Message<String, EchoBody<u32>, u32> = generator.poll(start).unwrap();
+        assert_eq!(first.source, "c1");
+        assert_eq!(first.destination, "n1");
+        assert!(generator.poll::<u32>(start).is_none());
+
+        let second = generator.poll::<u32>(start + Duration::from_millis(100));
+        assert_eq!(second.unwrap().destination, "n2");
+        assert!(generator
+            .poll::<u32>(start + Duration::from_millis(200))
+            .is_none());
+        assert_eq!(generator.in_flight(), 2);
+
+        let reply: Message<String, EchoBody<u32>, u32> = Message {
+            source: "n1".to_owned(),
+            destination: "c1".to_owned(),
+            body: Ok(EchoBody::Response {
+                in_reply_to: 1,
+                message_id: 1,
+                echo: "Please echo 1".to_owned(),
+            }),
+        };
+        generator.complete(&reply);
+        let third = generator.poll::<u32>(start + Duration::from_millis(200));
+        assert_eq!(third.unwrap().source, "c1");
+
+        let late = generator.poll::<u32>(start + Duration::from_secs(6));
+        assert_eq!(late.unwrap().source, "c2");
+        generator.complete(&Message::<String, _, u32> {
+            source: "n2".to_owned(),
+            destination: "c2".to_owned(),
+            body: Ok(EchoBody::Response {
+                in_reply_to: 2,
+                message_id: 2,
+                echo: "Please echo 2".to_owned(),
+            }),
+        });
+        assert_eq!(generator.in_flight(), 2);
+    }
+
+    #[test]
+    fn test_empty_configuration() {
+        let generator =
+            Generator::<String, _>::new(EchoWorkload::default(), &[], GeneratorConfig::default());
+        assert_eq!(generator.unwrap_err(), ConfigError::NoNodes);
+        let workload = KafkaWorkload::new(Vec::new(), 0.5);
+        assert_eq!(workload.unwrap_err(), ConfigError::NoKeys);
+    }
+}